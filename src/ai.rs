@@ -0,0 +1,136 @@
+use crate::{
+    board::*,
+    menu::playing,
+    pieces::*,
+    replay::Replay,
+    rules::Position,
+    search::{search, SearchResult},
+    uci::EnginePlayer,
+};
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Side played by the computer, if any, and how long it can think about a move
+pub struct AiPlayer {
+    pub color: Option<PieceColor>,
+    pub max_depth: u32,
+    pub think_time: Duration,
+}
+impl Default for AiPlayer {
+    fn default() -> Self {
+        Self {
+            color: None,
+            max_depth: 6,
+            think_time: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Search running on the task pool, with the position it was started from
+struct AiSearch {
+    task: Task<SearchResult>,
+    position: Position,
+    stop: Arc<AtomicBool>,
+}
+
+/// Starts a search on the `AsyncComputeTaskPool` when it's the computer's turn,
+/// so the frame doesn't stall while it thinks.
+/// Runs after the moves' commands are applied, so taken pieces are already marked
+#[allow(clippy::too_many_arguments)]
+fn start_ai_search(
+    mut commands: Commands,
+    ai_player: Res<AiPlayer>,
+    ai_search: Option<Res<AiSearch>>,
+    thread_pool: Res<AsyncComputeTaskPool>,
+    turn: Res<PlayerTurn>,
+    last_double_push: Res<LastDoublePush>,
+    clocks: Res<MoveClocks>,
+    game_over: Res<GameOver>,
+    replay: Option<Res<Replay>>,
+    engine_player: Option<Res<EnginePlayer>>,
+    pieces_query: Query<&Piece, Without<Taken>>,
+) {
+    if ai_player.color != Some(turn.0)
+        || ai_search.is_some()
+        || engine_player.is_some()
+        || game_over.0.is_some()
+        || replay.is_some()
+    {
+        return;
+    }
+
+    let pieces_vec: Vec<Piece> = pieces_query.iter().copied().collect();
+    let position = current_position(&pieces_vec, &turn, &last_double_push, &clocks);
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let task = {
+        let position = position.clone();
+        let stop = stop.clone();
+        let max_depth = ai_player.max_depth;
+        let deadline = Instant::now() + ai_player.think_time;
+        thread_pool.spawn(async move { search(&position, max_depth, Some(deadline), &stop) })
+    };
+
+    commands.insert_resource(AiSearch {
+        task,
+        position,
+        stop,
+    });
+}
+
+/// Plays the move once the search is done, unless the board changed in the meantime
+#[allow(clippy::too_many_arguments)]
+fn finish_ai_search(
+    mut commands: Commands,
+    ai_search: Option<ResMut<AiSearch>>,
+    turn: Res<PlayerTurn>,
+    last_double_push: Res<LastDoublePush>,
+    clocks: Res<MoveClocks>,
+    pieces_query: Query<&Piece, Without<Taken>>,
+    mut move_event: EventWriter<MoveEvent>,
+) {
+    let mut ai_search = match ai_search {
+        Some(ai_search) => ai_search,
+        None => return,
+    };
+
+    let pieces_vec: Vec<Piece> = pieces_query.iter().copied().collect();
+    let position = current_position(&pieces_vec, &turn, &last_double_push, &clocks);
+    if position != ai_search.position {
+        // A move was taken back or the game was reset, the result is useless
+        ai_search.stop.store(true, Ordering::Relaxed);
+        commands.remove_resource::<AiSearch>();
+        return;
+    }
+
+    if let Some(result) = future::block_on(future::poll_once(&mut ai_search.task)) {
+        if let Some(mv) = result.best_move {
+            move_event.send(MoveEvent(mv));
+        }
+        commands.remove_resource::<AiSearch>();
+    }
+}
+
+pub struct AiPlugin;
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        // The computer doesn't play in the menus or while the game is paused
+        app.init_resource::<AiPlayer>().add_system_set_to_stage(
+            CoreStage::PostUpdate,
+            SystemSet::new()
+                .with_run_criteria(playing)
+                .with_system(start_ai_search)
+                .with_system(finish_ai_search),
+        );
+    }
+}
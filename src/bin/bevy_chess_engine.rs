@@ -0,0 +1,265 @@
+//! The built-in search as a UCI engine on stdin and stdout, without any rendering,
+//! so it can be used from chess GUIs and in engine tournaments
+
+use bevy_chess::{
+    pieces::PieceColor,
+    rules::{Position, STARTING_FEN},
+    search::{search_with_info, SearchResult, MATE},
+};
+use std::{
+    io::{self, BufRead},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Depth searched when `go` doesn't set one, deep enough to only stop on time or `stop`
+const MAX_DEPTH: u32 = 64;
+
+/// Moves the remaining time is split between when `go` doesn't give `movestogo`
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+fn main() {
+    let mut position = Position::from_fen(STARTING_FEN).unwrap();
+    let mut search: Option<Search> = None;
+
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("uci") => {
+                println!("id name Bevy Chess");
+                println!("id author Bevy Chess contributors");
+                println!("uciok");
+            }
+            Some("isready") => println!("readyok"),
+            Some("ucinewgame") => {
+                stop_search(&mut search);
+                position = Position::from_fen(STARTING_FEN).unwrap();
+            }
+            Some("position") => match parse_position(&tokens.collect::<Vec<_>>()) {
+                Ok(new_position) => position = new_position,
+                Err(error) => println!("info string {}", error),
+            },
+            Some("go") => {
+                stop_search(&mut search);
+                let limits = parse_go(&tokens.collect::<Vec<_>>(), position.turn);
+                search = Some(start_search(position.clone(), limits));
+            }
+            Some("stop") => stop_search(&mut search),
+            Some("quit") => break,
+            _ => {}
+        }
+    }
+
+    stop_search(&mut search);
+}
+
+/// Search running on its own thread, so `stop` can be read while it thinks
+struct Search {
+    thread: JoinHandle<()>,
+    stop: Arc<AtomicBool>,
+}
+
+/// How deep and how long `go` allows the search to think
+#[derive(Debug, PartialEq)]
+struct Limits {
+    depth: u32,
+    time: Option<Duration>,
+}
+
+/// Reads the arguments of `position`: `startpos` or `fen <FEN>`, then the moves played from it
+fn parse_position(tokens: &[&str]) -> Result<Position, String> {
+    let moves_index = tokens
+        .iter()
+        .position(|token| *token == "moves")
+        .unwrap_or(tokens.len());
+
+    let mut position = match tokens.first() {
+        Some(&"startpos") => Position::from_fen(STARTING_FEN)?,
+        Some(&"fen") => Position::from_fen(&tokens[1..moves_index].join(" "))?,
+        _ => return Err("position needs startpos or fen".to_string()),
+    };
+
+    for uci in tokens.iter().skip(moves_index + 1) {
+        let mv = position.parse_uci(uci)?;
+        position.make_move(mv);
+    }
+
+    Ok(position)
+}
+
+/// Reads the arguments of `go`. With `wtime` and `btime`, the side to move spends
+/// a share of its remaining time plus most of its increment
+fn parse_go(tokens: &[&str], turn: PieceColor) -> Limits {
+    let value = |name: &str| -> Option<u64> {
+        let index = tokens.iter().position(|token| *token == name)?;
+        tokens.get(index + 1)?.parse().ok()
+    };
+
+    let (remaining, increment) = match turn {
+        PieceColor::White => (value("wtime"), value("winc")),
+        PieceColor::Black => (value("btime"), value("binc")),
+    };
+    let clock_time = remaining.map(|remaining| {
+        let moves_to_go = value("movestogo")
+            .unwrap_or(DEFAULT_MOVES_TO_GO as u64)
+            .max(1);
+        let budget = remaining / moves_to_go + increment.unwrap_or(0) * 3 / 4;
+        // Never risk more than half of what's left
+        budget.min(remaining / 2)
+    });
+
+    Limits {
+        depth: value("depth").map_or(MAX_DEPTH, |depth| depth as u32),
+        time: value("movetime").or(clock_time).map(Duration::from_millis),
+    }
+}
+
+fn start_search(position: Position, limits: Limits) -> Search {
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let stop = stop.clone();
+        thread::spawn(move || {
+            let start = Instant::now();
+            let deadline = limits.time.map(|time| start + time);
+            let result = search_with_info(&position, limits.depth, deadline, &stop, |result| {
+                println!("{}", info_line(result, start.elapsed()))
+            });
+
+            match result.best_move {
+                Some(mv) => println!("bestmove {}", mv.to_uci()),
+                // Mated or stalemated, there's nothing to play
+                None => println!("bestmove 0000"),
+            }
+        })
+    };
+
+    Search { thread, stop }
+}
+
+/// Stops the running search, if any, and waits for its `bestmove`
+fn stop_search(search: &mut Option<Search>) {
+    if let Some(search) = search.take() {
+        search.stop.store(true, Ordering::Relaxed);
+        let _ = search.thread.join();
+    }
+}
+
+fn info_line(result: &SearchResult, elapsed: Duration) -> String {
+    // Mates are given in moves, negative when the engine is the one getting mated
+    let score = if result.score.abs() >= MATE - MAX_DEPTH as i32 {
+        let moves = (MATE - result.score.abs() + 1) / 2;
+        format!("mate {}", moves * result.score.signum())
+    } else {
+        format!("cp {}", result.score)
+    };
+
+    let mut line = format!(
+        "info depth {} score {} nodes {} time {}",
+        result.depth,
+        score,
+        result.nodes,
+        elapsed.as_millis()
+    );
+    if let Some(mv) = result.best_move {
+        line.push_str(&format!(" pv {}", mv.to_uci()));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_positions() {
+        let position = parse_position(&["startpos", "moves", "e2e4", "e7e5"]).unwrap();
+        assert_eq!(
+            position.to_fen(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2"
+        );
+
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 12";
+        let tokens: Vec<&str> = ["fen"]
+            .iter()
+            .copied()
+            .chain(fen.split(' '))
+            .chain(["moves", "e8d7"])
+            .collect();
+        assert_eq!(
+            parse_position(&tokens).unwrap().to_fen(),
+            "8/3k4/8/8/8/8/4P3/4K3 w - - 1 13"
+        );
+
+        assert_eq!(
+            parse_position(&["startpos", "moves", "e2e5"]).err(),
+            Some("e2e5 is not a legal move".to_string())
+        );
+    }
+
+    #[test]
+    fn reads_go_limits() {
+        assert_eq!(
+            parse_go(&["depth", "5"], PieceColor::White),
+            Limits {
+                depth: 5,
+                time: None
+            }
+        );
+        assert_eq!(
+            parse_go(&["movetime", "1500"], PieceColor::Black),
+            Limits {
+                depth: MAX_DEPTH,
+                time: Some(Duration::from_millis(1500))
+            }
+        );
+        assert_eq!(
+            parse_go(
+                &["wtime", "60000", "btime", "3000", "winc", "2000", "binc", "0"],
+                PieceColor::White
+            ),
+            Limits {
+                depth: MAX_DEPTH,
+                time: Some(Duration::from_millis(3500))
+            }
+        );
+        assert_eq!(
+            parse_go(
+                &["wtime", "60000", "btime", "3000", "movestogo", "1"],
+                PieceColor::Black
+            ),
+            Limits {
+                depth: MAX_DEPTH,
+                time: Some(Duration::from_millis(1500))
+            }
+        );
+    }
+
+    #[test]
+    fn mates_are_given_in_moves() {
+        let result = SearchResult {
+            best_move: None,
+            score: MATE - 3,
+            depth: 3,
+            nodes: 10,
+        };
+        assert_eq!(
+            info_line(&result, Duration::from_millis(5)),
+            "info depth 3 score mate 2 nodes 10 time 5"
+        );
+
+        let result = SearchResult {
+            score: -(MATE - 2),
+            ..result
+        };
+        assert!(info_line(&result, Duration::ZERO).contains("score mate -1"));
+    }
+}
@@ -1,5 +1,12 @@
-use crate::pieces::*;
-use bevy::{app::AppExit, prelude::*};
+use crate::{
+    ai::AiPlayer,
+    menu::AppState,
+    pgn::{write_pgn, PgnTags},
+    pieces::*,
+    replay::Replay,
+    rules::{Move, Position},
+};
+use bevy::prelude::*;
 use bevy_mod_picking::*;
 
 #[derive(Component)]
@@ -49,27 +56,100 @@ fn create_board(
     }
 }
 
+/// Squares highlighted for the selected piece and the side to move.
+/// Only worked out again when the selection or the position changes, not every frame
+#[derive(Default)]
+struct SquareHighlights {
+    /// Squares the selected piece can move to, the same moves is_move_valid accepts
+    targets: Vec<(u8, u8)>,
+    /// Squares where the selected piece can take
+    captures: Vec<(u8, u8)>,
+    /// Square of the king of the side to move, when it's in check
+    king_in_check: Option<(u8, u8)>,
+}
+
+/// Works out the highlights in PostUpdate, once the pieces spawned and taken by a move are in place
+fn update_square_highlights(
+    selected_piece: Res<SelectedPiece>,
+    turn: Res<PlayerTurn>,
+    last_double_push: Res<LastDoublePush>,
+    history: Res<MoveHistory>,
+    mut highlights: ResMut<SquareHighlights>,
+    pieces_query: Query<&Piece, Without<Taken>>,
+) {
+    if !selected_piece.is_changed() && !turn.is_changed() && !history.is_changed() {
+        return;
+    }
+
+    let pieces_vec: Vec<Piece> = pieces_query.iter().copied().collect();
+    let position = Position::from_pieces(&pieces_vec, turn.0, last_double_push.0);
+
+    let mut targets = Vec::new();
+    let mut captures = Vec::new();
+    if let Some(piece) = selected_piece
+        .entity
+        .and_then(|entity| pieces_query.get(entity).ok())
+    {
+        for mv in position.legal_moves_from((piece.x, piece.y)) {
+            // Pawns moving sideways always take, even when the square is empty (en passant)
+            let takes = position.piece_at(mv.to).is_some()
+                || (piece.piece_type == PieceType::Pawn && mv.from.1 != mv.to.1);
+            if takes {
+                captures.push(mv.to);
+            } else {
+                targets.push(mv.to);
+            }
+        }
+    }
+
+    *highlights = SquareHighlights {
+        targets,
+        captures,
+        king_in_check: if position.is_in_check(turn.0) {
+            position.king_square(turn.0)
+        } else {
+            None
+        },
+    };
+}
+
 fn color_squares(
     selected_square: Res<SelectedSquare>,
+    highlights: Res<SquareHighlights>,
+    history: Res<MoveHistory>,
     materials: Res<SquareMaterials>,
+    keyboard_cursor: Res<KeyboardCursor>,
     mut query: Query<(Entity, &Square, &mut Handle<StandardMaterial>)>,
     picking_camera_query: Query<&PickingCamera>,
 ) {
     // Get entity under the cursor, if there is one
     let top_entity = match picking_camera_query.iter().last() {
-        Some(picking_camera) => match picking_camera.intersect_top() {
-            Some((entity, _intersection)) => Some(entity),
-            None => None,
-        },
+        Some(picking_camera) => picking_camera
+            .intersect_top()
+            .map(|(entity, _intersection)| entity),
         None => None,
     };
 
+    let last_move = history.moves.last().map(|played_move| played_move.mv);
+
     for (entity, square, mut material) in query.iter_mut() {
-        // Change the material
-        *material = if Some(entity) == top_entity {
+        let square_position = (square.x, square.y);
+        // Change the material, the first state that applies wins
+        *material = if Some(entity) == top_entity || keyboard_cursor.square == Some(square_position)
+        {
             materials.highlight_color.clone()
         } else if Some(entity) == selected_square.entity {
             materials.selected_color.clone()
+        } else if highlights.captures.contains(&square_position) {
+            materials.capture_color.clone()
+        } else if highlights.targets.contains(&square_position) {
+            materials.move_color.clone()
+        } else if highlights.king_in_check == Some(square_position) {
+            materials.check_color.clone()
+        } else if last_move.map_or(false, |mv| mv.from == square_position) {
+            materials.last_move_from_color.clone()
+        } else if last_move.map_or(false, |mv| mv.to == square_position) {
+            materials.last_move_to_color.clone()
         } else if square.is_white() {
             materials.white_color.clone()
         } else {
@@ -81,6 +161,15 @@ fn color_squares(
 struct SquareMaterials {
     highlight_color: Handle<StandardMaterial>,
     selected_color: Handle<StandardMaterial>,
+    /// Squares the selected piece can move to
+    move_color: Handle<StandardMaterial>,
+    /// Squares where the selected piece can take
+    capture_color: Handle<StandardMaterial>,
+    /// Squares the last move left and reached
+    last_move_from_color: Handle<StandardMaterial>,
+    last_move_to_color: Handle<StandardMaterial>,
+    /// Glows under a king in check
+    check_color: Handle<StandardMaterial>,
     black_color: Handle<StandardMaterial>,
     white_color: Handle<StandardMaterial>,
 }
@@ -94,6 +183,15 @@ impl FromWorld for SquareMaterials {
         SquareMaterials {
             highlight_color: materials.add(Color::rgb(0.8, 0.3, 0.3).into()),
             selected_color: materials.add(Color::rgb(0.9, 0.1, 0.1).into()),
+            move_color: materials.add(Color::rgb(0.3, 0.7, 0.3).into()),
+            capture_color: materials.add(Color::rgb(0.9, 0.5, 0.1).into()),
+            last_move_from_color: materials.add(Color::rgb(0.6, 0.6, 0.3).into()),
+            last_move_to_color: materials.add(Color::rgb(0.8, 0.8, 0.3).into()),
+            check_color: materials.add(StandardMaterial {
+                base_color: Color::rgb(1., 0., 0.),
+                emissive: Color::rgb(0.6, 0., 0.),
+                ..Default::default()
+            }),
             black_color: materials.add(Color::rgb(0., 0.1, 0.1).into()),
             white_color: materials.add(Color::rgb(1., 0.9, 0.9).into()),
         }
@@ -109,6 +207,125 @@ struct SelectedPiece {
     entity: Option<Entity>,
 }
 
+/// Square picked with the keyboard, it's colored like the square under the mouse.
+/// Hidden until an arrow key or WASD is pressed, and again once the mouse is clicked
+#[derive(Default)]
+struct KeyboardCursor {
+    square: Option<(u8, u8)>,
+}
+
+/// Square of the pawn that moved two squares on the last move, it can be taken en passant
+#[derive(Default)]
+pub struct LastDoublePush(pub Option<(u8, u8)>);
+
+/// Halfmove clock and fullmove number, the last two fields of a FEN
+pub struct MoveClocks {
+    /// Half moves since the last capture or pawn move
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+}
+impl Default for MoveClocks {
+    fn default() -> Self {
+        Self {
+            halfmove_clock: 0,
+            fullmove_number: 1,
+        }
+    }
+}
+
+/// A move as it was played, with the position it was played from so it can be taken back.
+/// Undo shows that position again, which respawns the pieces the move took
+#[derive(Clone)]
+pub struct PlayedMove {
+    pub mv: Move,
+    /// Piece taken by the move, pawns taken en passant included
+    pub captured: Option<Piece>,
+    /// Position before the move, with the turn, castling rights, en passant square and clocks
+    pub position_before: Position,
+}
+impl PlayedMove {
+    pub fn new(position_before: &Position, mv: Move) -> Self {
+        // Pawns moving diagonally to an empty square take en passant
+        let capture_square = match position_before.piece_at(mv.to) {
+            None if mv.from.1 != mv.to.1
+                && position_before.piece_at(mv.from).map(|piece| piece.1)
+                    == Some(PieceType::Pawn) =>
+            {
+                (mv.from.0, mv.to.1)
+            }
+            _ => mv.to,
+        };
+
+        Self {
+            mv,
+            captured: position_before
+                .to_pieces()
+                .into_iter()
+                .find(|piece| (piece.x, piece.y) == capture_square),
+            position_before: position_before.clone(),
+        }
+    }
+
+    /// Position once the move is played
+    pub fn position_after(&self) -> Position {
+        let mut position = self.position_before.clone();
+        position.make_move(self.mv);
+        position
+    }
+}
+
+/// Moves played since the starting position, and the ones taken back that can be played again
+#[derive(Default)]
+pub struct MoveHistory {
+    pub moves: Vec<PlayedMove>,
+    /// Moves taken back with undo, the last one is the next to redo
+    pub undone: Vec<PlayedMove>,
+}
+impl MoveHistory {
+    /// Every move of the game in the order it was played, the ones taken back included
+    pub fn all_moves(&self) -> impl Iterator<Item = &PlayedMove> {
+        self.moves.iter().chain(self.undone.iter().rev())
+    }
+
+    /// Takes back or plays again moves until `count` of them are played,
+    /// and returns the position reached. None if there aren't that many moves
+    pub fn go_to(&mut self, count: usize) -> Option<Position> {
+        if count > self.moves.len() + self.undone.len() {
+            return None;
+        }
+
+        if count < self.moves.len() {
+            let taken_back = self.moves.split_off(count);
+            self.undone.extend(taken_back.into_iter().rev());
+        } else {
+            let replayed = self
+                .undone
+                .split_off(self.undone.len() + self.moves.len() - count);
+            self.moves.extend(replayed.into_iter().rev());
+        }
+
+        match (self.moves.last(), self.undone.last()) {
+            (Some(last_move), _) => Some(last_move.position_after()),
+            (None, Some(next_move)) => Some(next_move.position_before.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Sent to play a legal move of the side to move on the board
+pub struct MoveEvent(pub Move);
+
+/// Sent to show the position after the first moves of the game, as many as given
+pub struct JumpToMoveEvent(pub usize);
+
+/// Move of a pawn to the last row, waiting for the player to pick its promotion.
+/// The move is played, and the turn changes, once the piece is chosen
+#[derive(Default)]
+pub struct PendingPromotion(pub Option<Move>);
+
+/// Sent by the promotion picker with the piece type chosen for the pending pawn
+pub struct PromotionEvent(pub PieceType);
+
 #[derive(Component)]
 pub struct PlayerTurn(pub PieceColor);
 impl Default for PlayerTurn {
@@ -116,19 +333,178 @@ impl Default for PlayerTurn {
         Self(PieceColor::White)
     }
 }
-impl PlayerTurn {
-    fn change(&mut self) {
-        self.0 = match self.0 {
-            PieceColor::White => PieceColor::Black,
-            PieceColor::Black => PieceColor::White,
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GameResult {
+    /// The side to move is in check and can't escape it, this color won
+    Checkmate(PieceColor),
+    /// The side to move has no legal moves but isn't in check, it's a draw
+    Stalemate,
+    /// The side to move ran out of time, this color won
+    OutOfTime(PieceColor),
+}
+
+impl GameResult {
+    /// Result as written in PGN
+    pub fn pgn_result(&self) -> &'static str {
+        match self {
+            GameResult::Checkmate(PieceColor::White) | GameResult::OutOfTime(PieceColor::White) => {
+                "1-0"
+            }
+            GameResult::Checkmate(PieceColor::Black) | GameResult::OutOfTime(PieceColor::Black) => {
+                "0-1"
+            }
+            GameResult::Stalemate => "1/2-1/2",
+        }
+    }
+
+    /// Result as announced to the players
+    pub fn message(&self) -> &'static str {
+        match self {
+            GameResult::Checkmate(PieceColor::White) => "Checkmate! White won!",
+            GameResult::Checkmate(PieceColor::Black) => "Checkmate! Black won!",
+            GameResult::Stalemate => "Stalemate! It's a draw",
+            GameResult::OutOfTime(PieceColor::White) => "Black ran out of time! White won!",
+            GameResult::OutOfTime(PieceColor::Black) => "White ran out of time! Black won!",
+        }
+    }
+}
+
+/// Sent once when the game ends
+pub struct GameOverEvent(pub GameResult);
+
+/// Sent to start again from the starting position
+pub struct NewGameEvent;
+
+/// Result of the game, stays None while it is still being played
+#[derive(Default)]
+pub struct GameOver(pub Option<GameResult>);
+
+/// Builds the position that is currently on the board
+pub fn current_position(
+    pieces: &[Piece],
+    turn: &PlayerTurn,
+    last_double_push: &LastDoublePush,
+    clocks: &MoveClocks,
+) -> Position {
+    let mut position = Position::from_pieces(pieces, turn.0, last_double_push.0);
+    position.halfmove_clock = clocks.halfmove_clock;
+    position.fullmove_number = clocks.fullmove_number;
+    position
+}
+
+/// Sets the side to move, en passant square and clocks to the ones of `position`
+pub fn set_game_state(
+    position: &Position,
+    turn: &mut PlayerTurn,
+    last_double_push: &mut LastDoublePush,
+    clocks: &mut MoveClocks,
+) {
+    turn.0 = position.turn;
+    last_double_push.0 = position.last_double_push();
+    clocks.halfmove_clock = position.halfmove_clock;
+    clocks.fullmove_number = position.fullmove_number;
+}
+
+/// Moves, spawns and despawns piece entities so the board shows `position`.
+/// Pieces that only change square keep their entity, so `move_pieces` slides them there
+pub fn show_position(
+    commands: &mut Commands,
+    meshes: &PieceMeshes,
+    pieces_query: &mut Query<(Entity, &mut Piece), Without<Taken>>,
+    position: &Position,
+) {
+    let mut targets = position.to_pieces();
+    let mut unmatched = Vec::new();
+
+    // Pieces that are already in their square stay there
+    for (entity, mut piece) in pieces_query.iter_mut() {
+        let same_piece = targets.iter().position(|target| {
+            (target.x, target.y) == (piece.x, piece.y)
+                && target.color == piece.color
+                && target.piece_type == piece.piece_type
+        });
+        match same_piece {
+            Some(index) => piece.has_moved = targets.swap_remove(index).has_moved,
+            None => unmatched.push(entity),
+        }
+    }
+
+    // The rest move to the closest square that needs a piece like them
+    for target in targets {
+        let closest = unmatched
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entity)| {
+                let (_, piece) = pieces_query.get(*entity).ok()?;
+                if piece.color != target.color || piece.piece_type != target.piece_type {
+                    return None;
+                }
+                let distance =
+                    (piece.x as i8 - target.x as i8).abs() + (piece.y as i8 - target.y as i8).abs();
+                Some((index, distance))
+            })
+            .min_by_key(|(_, distance)| *distance);
+
+        match closest {
+            Some((index, _)) => {
+                let entity = unmatched.swap_remove(index);
+                if let Ok((_, mut piece)) = pieces_query.get_mut(entity) {
+                    *piece = target;
+                }
+            }
+            None => {
+                spawn_piece(commands, meshes, target);
+            }
         }
     }
+
+    // Whatever is left isn't in the position anymore
+    for entity in unmatched {
+        commands.entity(entity).insert(Taken);
+    }
+}
+
+fn setup_starting_position(
+    starting_position: Res<StartingPosition>,
+    mut turn: ResMut<PlayerTurn>,
+    mut last_double_push: ResMut<LastDoublePush>,
+    mut clocks: ResMut<MoveClocks>,
+) {
+    set_game_state(
+        &starting_position.0,
+        &mut turn,
+        &mut last_double_push,
+        &mut clocks,
+    );
 }
 
+/// The board is locked while the promotion picker is open, once the game is over,
+/// while replaying a game and while the computer plays
+pub fn board_locked(
+    pending_promotion: &PendingPromotion,
+    game_over: &GameOver,
+    replaying: bool,
+    ai_player: Option<&AiPlayer>,
+    turn: &PlayerTurn,
+) -> bool {
+    pending_promotion.0.is_some()
+        || game_over.0.is_some()
+        || replaying
+        || ai_player.map(|ai_player| ai_player.color) == Some(Some(turn.0))
+}
+
+#[allow(clippy::too_many_arguments)]
 fn select_square(
     mouse_button_inputs: Res<Input<MouseButton>>,
     mut selected_square: ResMut<SelectedSquare>,
     mut selected_piece: ResMut<SelectedPiece>,
+    mut keyboard_cursor: ResMut<KeyboardCursor>,
+    pending_promotion: Res<PendingPromotion>,
+    game_over: Res<GameOver>,
+    turn: Res<PlayerTurn>,
+    replay: Option<Res<Replay>>,
+    ai_player: Option<Res<AiPlayer>>,
     squares_query: Query<&Square>,
     picking_camera_query: Query<&PickingCamera>,
 ) {
@@ -137,6 +513,16 @@ fn select_square(
         return;
     }
 
+    // Playing with the mouse, the keyboard cursor is out of the way
+    if keyboard_cursor.square.is_some() {
+        keyboard_cursor.square = None;
+    }
+
+    let (replaying, ai_player) = (replay.is_some(), ai_player.as_deref());
+    if board_locked(&pending_promotion, &game_over, replaying, ai_player, &turn) {
+        return;
+    }
+
     // Get the square under the cursor and set it as the selected
     if let Some(picking_camera) = picking_camera_query.iter().last() {
         if let Some((square_entity, _intersection)) = picking_camera.intersect_top() {
@@ -152,6 +538,72 @@ fn select_square(
     }
 }
 
+/// Arrow keys or WASD move the keyboard cursor, Enter or Space select the square under it
+/// the same way a click does. The first key press shows the cursor on the selected square,
+/// or on the king's starting square
+#[allow(clippy::too_many_arguments)]
+fn move_keyboard_cursor(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut keyboard_cursor: ResMut<KeyboardCursor>,
+    mut selected_square: ResMut<SelectedSquare>,
+    pending_promotion: Res<PendingPromotion>,
+    game_over: Res<GameOver>,
+    turn: Res<PlayerTurn>,
+    replay: Option<Res<Replay>>,
+    ai_player: Option<Res<AiPlayer>>,
+    squares_query: Query<(Entity, &Square)>,
+) {
+    // The arrow keys step through replays instead
+    let (replaying, ai_player) = (replay.is_some(), ai_player.as_deref());
+    if board_locked(&pending_promotion, &game_over, replaying, ai_player, &turn) {
+        return;
+    }
+
+    let pressed = |keys: [KeyCode; 2]| keys.iter().any(|key| keyboard_input.just_pressed(*key));
+    // Up goes towards Black's side and right towards the h column, as seen from White's side
+    let (dx, dy) = if pressed([KeyCode::Up, KeyCode::W]) {
+        (1, 0)
+    } else if pressed([KeyCode::Down, KeyCode::S]) {
+        (-1, 0)
+    } else if pressed([KeyCode::Left, KeyCode::A]) {
+        (0, -1)
+    } else if pressed([KeyCode::Right, KeyCode::D]) {
+        (0, 1)
+    } else {
+        (0, 0)
+    };
+
+    if (dx, dy) != (0, 0) {
+        keyboard_cursor.square = Some(match keyboard_cursor.square {
+            Some((x, y)) => (
+                (x as i8 + dx).clamp(0, 7) as u8,
+                (y as i8 + dy).clamp(0, 7) as u8,
+            ),
+            None => selected_square
+                .entity
+                .and_then(|entity| squares_query.get(entity).ok())
+                .map_or(
+                    match turn.0 {
+                        PieceColor::White => (0, 4),
+                        PieceColor::Black => (7, 4),
+                    },
+                    |(_, square)| (square.x, square.y),
+                ),
+        });
+    }
+
+    let cursor = match keyboard_cursor.square {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    if pressed([KeyCode::Return, KeyCode::Space]) {
+        selected_square.entity = squares_query
+            .iter()
+            .find(|(_, square)| (square.x, square.y) == cursor)
+            .map(|(square_entity, _)| square_entity);
+    }
+}
+
 fn select_piece(
     selected_square: Res<SelectedSquare>,
     mut selected_piece: ResMut<SelectedPiece>,
@@ -187,13 +639,130 @@ fn select_piece(
     }
 }
 
-fn move_piece(
+/// Point of the board under the cursor
+fn cursor_on_board(picking_camera_query: &Query<&PickingCamera>) -> Option<Vec3> {
+    let ray = picking_camera_query.iter().last()?.ray()?;
+    // The ray has to go down to reach the board
+    if ray.direction().y >= 0. {
+        return None;
+    }
+    let distance = -ray.origin().y / ray.direction().y;
+    Some(ray.origin() + ray.direction() * distance)
+}
+
+/// Square under a point of the board, None outside of it
+fn square_at(point: Vec3) -> Option<(u8, u8)> {
+    let (x, y) = (point.x.round(), point.z.round());
+    if (0. ..=7.).contains(&x) && (0. ..=7.).contains(&y) {
+        Some((x as u8, y as u8))
+    } else {
+        None
+    }
+}
+
+/// Pressing on a piece of the side to move picks it up, so it can be dragged
+fn start_drag(
     mut commands: Commands,
+    mouse_button_inputs: Res<Input<MouseButton>>,
+    selected_piece: Res<SelectedPiece>,
+) {
+    if !mouse_button_inputs.just_pressed(MouseButton::Left) || !selected_piece.is_changed() {
+        return;
+    }
+
+    if let Some(piece_entity) = selected_piece.entity {
+        commands.entity(piece_entity).insert(Dragged);
+    }
+}
+
+/// The dragged piece follows the cursor over the board
+fn drag_piece(
+    picking_camera_query: Query<&PickingCamera>,
+    mut dragged_query: Query<&mut Transform, With<Dragged>>,
+) {
+    let point = match cursor_on_board(&picking_camera_query) {
+        Some(point) => point,
+        None => return,
+    };
+
+    for mut transform in dragged_query.iter_mut() {
+        transform.translation = point;
+    }
+}
+
+/// Releasing the dragged piece over a square plays the move there, the same as clicking
+/// the square would. Illegal moves snap the piece back to where it was picked up, and so do
+/// drops on a board that got locked during the drag
+#[allow(clippy::too_many_arguments)]
+fn drop_piece(
+    mut commands: Commands,
+    mouse_button_inputs: Res<Input<MouseButton>>,
+    mut selected_square: ResMut<SelectedSquare>,
+    pending_promotion: Res<PendingPromotion>,
+    game_over: Res<GameOver>,
+    turn: Res<PlayerTurn>,
+    last_double_push: Res<LastDoublePush>,
+    replay: Option<Res<Replay>>,
+    ai_player: Option<Res<AiPlayer>>,
+    picking_camera_query: Query<&PickingCamera>,
+    squares_query: Query<(Entity, &Square)>,
+    pieces_query: Query<&Piece, Without<Taken>>,
+    mut dragged_query: Query<(Entity, &Piece, &mut Transform), With<Dragged>>,
+    mut reset_selected_event: EventWriter<ResetSelectedEvent>,
+) {
+    if mouse_button_inputs.pressed(MouseButton::Left) {
+        return;
+    }
+
+    let (replaying, ai_player) = (replay.is_some(), ai_player.as_deref());
+    let locked = board_locked(&pending_promotion, &game_over, replaying, ai_player, &turn);
+
+    for (piece_entity, piece, mut transform) in dragged_query.iter_mut() {
+        commands.entity(piece_entity).remove::<Dragged>();
+        transform.translation = Vec3::new(piece.x as f32, 0., piece.y as f32);
+
+        if locked {
+            reset_selected_event.send(ResetSelectedEvent);
+            continue;
+        }
+
+        let target = match cursor_on_board(&picking_camera_query).and_then(square_at) {
+            Some(target) => target,
+            None => {
+                // Dropped outside the board
+                reset_selected_event.send(ResetSelectedEvent);
+                continue;
+            }
+        };
+        // Dropped back where it was, it stays selected to be moved with a click
+        if target == (piece.x, piece.y) {
+            continue;
+        }
+
+        let pieces_vec: Vec<Piece> = pieces_query.iter().copied().collect();
+        let position = Position::from_pieces(&pieces_vec, turn.0, last_double_push.0);
+        if piece.is_move_valid(target, &position) {
+            transform.translation = Vec3::new(target.0 as f32, 0., target.1 as f32);
+        }
+
+        // move_piece plays the move, or drops the selection if it's illegal
+        selected_square.entity = squares_query
+            .iter()
+            .find(|(_, square)| (square.x, square.y) == target)
+            .map(|(square_entity, _)| square_entity);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn move_piece(
     selected_square: Res<SelectedSquare>,
     selected_piece: Res<SelectedPiece>,
-    mut turn: ResMut<PlayerTurn>,
+    turn: Res<PlayerTurn>,
+    last_double_push: Res<LastDoublePush>,
+    mut pending_promotion: ResMut<PendingPromotion>,
     squares_query: Query<&Square>,
-    mut pieces_query: Query<(Entity, &mut Piece)>,
+    pieces_query: Query<&Piece, Without<Taken>>,
+    mut move_event: EventWriter<MoveEvent>,
     mut reset_selected_event: EventWriter<ResetSelectedEvent>,
 ) {
     if !selected_square.is_changed() {
@@ -213,40 +782,67 @@ fn move_piece(
     };
 
     if let Some(selected_piece_entity) = selected_piece.entity {
-        let pieces_vec = pieces_query.iter_mut().map(|(_, piece)| *piece).collect();
-        let pieces_entity_vec = pieces_query
-            .iter_mut()
-            .map(|(entity, piece)| (entity, *piece))
-            .collect::<Vec<(Entity, Piece)>>();
+        let piece = if let Ok(piece) = pieces_query.get(selected_piece_entity) {
+            piece
+        } else {
+            return;
+        };
+
+        let pieces_vec: Vec<Piece> = pieces_query.iter().copied().collect();
+        let position = Position::from_pieces(&pieces_vec, turn.0, last_double_push.0);
+
         // Move the selected piece to the selected square
-        let mut piece =
-            if let Ok((_piece_entity, piece)) = pieces_query.get_mut(selected_piece_entity) {
-                piece
-            } else {
-                return;
+        if piece.is_move_valid((square.x, square.y), &position) {
+            let mv = Move {
+                from: (piece.x, piece.y),
+                to: (square.x, square.y),
+                promotion: None,
             };
 
-        if piece.is_move_valid((square.x, square.y), pieces_vec) {
-            // Check if a piece of the opposite color exists in this square and despawn it
-            for (other_entity, other_piece) in pieces_entity_vec {
-                if other_piece.x == square.x
-                    && other_piece.y == square.y
-                    && other_piece.color != piece.color
-                {
-                    // Mark the piece as taken
-                    commands.entity(other_entity).insert(Taken);
-                }
+            // Pawns reaching the last row wait for the player to pick their promotion
+            if piece.piece_type == PieceType::Pawn && (square.x == 0 || square.x == 7) {
+                pending_promotion.0 = Some(mv);
+            } else {
+                move_event.send(MoveEvent(mv));
             }
+        }
 
-            // Move piece
-            piece.x = square.x;
-            piece.y = square.y;
+        reset_selected_event.send(ResetSelectedEvent);
+    }
+}
 
-            // Change turn
-            turn.change();
+/// Plays the moves sent with `MoveEvent` on the board, and records them in the history
+#[allow(clippy::too_many_arguments)]
+fn apply_move(
+    mut commands: Commands,
+    mut event_reader: EventReader<MoveEvent>,
+    meshes: Res<PieceMeshes>,
+    mut turn: ResMut<PlayerTurn>,
+    mut last_double_push: ResMut<LastDoublePush>,
+    mut clocks: ResMut<MoveClocks>,
+    mut history: ResMut<MoveHistory>,
+    mut pieces_query: Query<(Entity, &mut Piece), Without<Taken>>,
+) {
+    for event in event_reader.iter() {
+        let pieces_vec: Vec<Piece> = pieces_query.iter().map(|(_, piece)| *piece).collect();
+        let position = current_position(&pieces_vec, &turn, &last_double_push, &clocks);
+        if !position.legal_moves().contains(&event.0) {
+            continue;
         }
 
-        reset_selected_event.send(ResetSelectedEvent);
+        let played_move = PlayedMove::new(&position, event.0);
+        let position_after = played_move.position_after();
+        show_position(&mut commands, &meshes, &mut pieces_query, &position_after);
+        set_game_state(
+            &position_after,
+            &mut turn,
+            &mut last_double_push,
+            &mut clocks,
+        );
+
+        history.moves.push(played_move);
+        // A new move replaces the ones that were taken back
+        history.undone.clear();
     }
 }
 
@@ -263,56 +859,343 @@ fn reset_selected(
     }
 }
 
-#[derive(Component)]
-struct Taken;
-fn despawn_taken_pieces(
+fn promote_pawn(
+    mut event_reader: EventReader<PromotionEvent>,
+    mut pending_promotion: ResMut<PendingPromotion>,
+    mut move_event: EventWriter<MoveEvent>,
+) {
+    for event in event_reader.iter() {
+        if let Some(mv) = pending_promotion.0.take() {
+            move_event.send(MoveEvent(Move {
+                promotion: Some(event.0),
+                ..mv
+            }));
+        }
+    }
+}
+
+/// Take back the last move with Ctrl+Z, and play it again with Ctrl+Y
+#[allow(clippy::too_many_arguments)]
+fn undo_redo(
     mut commands: Commands,
-    mut app_exit_events: EventWriter<AppExit>,
-    query: Query<(Entity, &Piece, &Taken)>,
+    keyboard_input: Res<Input<KeyCode>>,
+    meshes: Res<PieceMeshes>,
+    mut turn: ResMut<PlayerTurn>,
+    mut last_double_push: ResMut<LastDoublePush>,
+    mut clocks: ResMut<MoveClocks>,
+    mut history: ResMut<MoveHistory>,
+    mut game_over: ResMut<GameOver>,
+    mut pending_promotion: ResMut<PendingPromotion>,
+    replay: Option<Res<Replay>>,
+    ai_player: Option<Res<AiPlayer>>,
+    mut pieces_query: Query<(Entity, &mut Piece), Without<Taken>>,
+    mut reset_selected_event: EventWriter<ResetSelectedEvent>,
 ) {
-    for (entity, piece, _taken) in query.iter() {
-        // If the king is taken, we should exit
-        if piece.piece_type == PieceType::King {
-            println!(
-                "{} won! Thanks for playing!",
-                match piece.color {
-                    PieceColor::White => "Black",
-                    PieceColor::Black => "White",
-                }
-            );
-            app_exit_events.send(AppExit);
+    let control =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    if !control || replay.is_some() {
+        return;
+    }
+
+    // Against the computer, moves are taken back and played again until it's the player's turn
+    let ai_color = ai_player.and_then(|ai_player| ai_player.color);
+    let mut position = None;
+
+    if keyboard_input.just_pressed(KeyCode::Z) {
+        // A pawn waiting for its promotion hasn't moved yet, closing the picker is enough
+        if pending_promotion.0.take().is_some() {
+            return;
+        }
+
+        while let Some(played_move) = history.moves.pop() {
+            let position_before = played_move.position_before.clone();
+            history.undone.push(played_move);
+            let computer_turn = ai_color == Some(position_before.turn);
+            position = Some(position_before);
+            if !computer_turn {
+                break;
+            }
         }
+    } else if keyboard_input.just_pressed(KeyCode::Y) && pending_promotion.0.is_none() {
+        while let Some(played_move) = history.undone.pop() {
+            let position_after = played_move.position_after();
+            history.moves.push(played_move);
+            let computer_turn = ai_color == Some(position_after.turn);
+            position = Some(position_after);
+            if !computer_turn {
+                break;
+            }
+        }
+    }
+
+    let position = match position {
+        Some(position) => position,
+        None => return,
+    };
+
+    show_position(&mut commands, &meshes, &mut pieces_query, &position);
+    set_game_state(&position, &mut turn, &mut last_double_push, &mut clocks);
+    // Taking back the last move of a finished game lets it go on
+    game_over.0 = None;
+    reset_selected_event.send(ResetSelectedEvent);
+}
 
+/// Despawns every piece and spawns them again in the starting position,
+/// forgetting the moves, the selection and the result of the previous game
+#[allow(clippy::too_many_arguments)]
+fn new_game(
+    mut commands: Commands,
+    mut event_reader: EventReader<NewGameEvent>,
+    meshes: Res<PieceMeshes>,
+    starting_position: Res<StartingPosition>,
+    mut turn: ResMut<PlayerTurn>,
+    mut last_double_push: ResMut<LastDoublePush>,
+    mut clocks: ResMut<MoveClocks>,
+    mut history: ResMut<MoveHistory>,
+    mut game_over: ResMut<GameOver>,
+    mut pending_promotion: ResMut<PendingPromotion>,
+    mut selected_square: ResMut<SelectedSquare>,
+    mut selected_piece: ResMut<SelectedPiece>,
+    pieces_query: Query<Entity, With<Piece>>,
+) {
+    if event_reader.iter().last().is_none() {
+        return;
+    }
+
+    for entity in pieces_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    spawn_pieces(&mut commands, &meshes, &starting_position.0);
+
+    set_game_state(
+        &starting_position.0,
+        &mut turn,
+        &mut last_double_push,
+        &mut clocks,
+    );
+    *history = MoveHistory::default();
+    game_over.0 = None;
+    pending_promotion.0 = None;
+    selected_square.entity = None;
+    selected_piece.entity = None;
+}
+
+/// Shows the position after the moves picked in the move list.
+/// The moves after it stay in the history, they can be played again with redo
+#[allow(clippy::too_many_arguments)]
+fn jump_to_move(
+    mut commands: Commands,
+    mut event_reader: EventReader<JumpToMoveEvent>,
+    meshes: Res<PieceMeshes>,
+    mut turn: ResMut<PlayerTurn>,
+    mut last_double_push: ResMut<LastDoublePush>,
+    mut clocks: ResMut<MoveClocks>,
+    mut history: ResMut<MoveHistory>,
+    mut game_over: ResMut<GameOver>,
+    mut pending_promotion: ResMut<PendingPromotion>,
+    replay: Option<Res<Replay>>,
+    mut pieces_query: Query<(Entity, &mut Piece), Without<Taken>>,
+    mut reset_selected_event: EventWriter<ResetSelectedEvent>,
+) {
+    let count = match event_reader.iter().last() {
+        Some(event) => event.0,
+        None => return,
+    };
+    // Replays jump on their own
+    if replay.is_some() || count == history.moves.len() {
+        return;
+    }
+
+    let position = match history.go_to(count) {
+        Some(position) => position,
+        None => return,
+    };
+    show_position(&mut commands, &meshes, &mut pieces_query, &position);
+    set_game_state(&position, &mut turn, &mut last_double_push, &mut clocks);
+    game_over.0 = None;
+    pending_promotion.0 = None;
+    reset_selected_event.send(ResetSelectedEvent);
+}
+
+#[derive(Component)]
+pub struct Taken;
+fn despawn_taken_pieces(mut commands: Commands, query: Query<(Entity, &Taken)>) {
+    for (entity, _taken) in query.iter() {
         // Despawn piece and children
         commands.entity(entity).despawn_recursive();
     }
 }
 
+// Component for the models of the captured pieces lined up beside the board
+#[derive(Component)]
+struct CapturedPiece;
+
+/// Line up the pieces captured so far beside the board, the most valuable first:
+/// Black's pieces on White's left, White's pieces on Black's left
+fn show_captured_pieces(
+    mut commands: Commands,
+    meshes: Res<PieceMeshes>,
+    history: Res<MoveHistory>,
+    captured_query: Query<Entity, With<CapturedPiece>>,
+) {
+    if !history.is_changed() {
+        return;
+    }
+
+    for entity in captured_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    for color in [PieceColor::White, PieceColor::Black] {
+        let mut captured: Vec<Piece> = history
+            .moves
+            .iter()
+            .filter_map(|played_move| played_move.captured)
+            .filter(|piece| piece.color == color)
+            .collect();
+        captured.sort_by_key(|piece| -piece.piece_type.points());
+
+        for (i, piece) in captured.into_iter().enumerate() {
+            // Two columns of eight along the side of the board
+            let along = (i % 8) as f32;
+            let across = (i / 8) as f32 * 0.6;
+            let translation = match color {
+                PieceColor::White => Vec3::new(7. - along, 0., 8. + across),
+                PieceColor::Black => Vec3::new(along, 0., -1. - across),
+            };
+
+            // Only the model is kept, it's not a piece of the game anymore
+            let entity = spawn_piece(&mut commands, &meshes, piece);
+            commands
+                .entity(entity)
+                .remove::<Piece>()
+                .insert(CapturedPiece)
+                .insert(Transform {
+                    translation,
+                    scale: Vec3::splat(0.6),
+                    ..Default::default()
+                });
+        }
+    }
+}
+
+/// After every move, look for checkmate or stalemate of the side to move.
+/// Runs after the moves' commands are applied, so taken pieces are already marked
+fn check_game_over(
+    turn: Res<PlayerTurn>,
+    last_double_push: Res<LastDoublePush>,
+    mut game_over: ResMut<GameOver>,
+    mut game_over_event: EventWriter<GameOverEvent>,
+    replay: Option<Res<Replay>>,
+    pieces_query: Query<&Piece, Without<Taken>>,
+) {
+    if !turn.is_changed() || game_over.0.is_some() || replay.is_some() {
+        return;
+    }
+
+    let pieces_vec: Vec<Piece> = pieces_query.iter().copied().collect();
+    let position = Position::from_pieces(&pieces_vec, turn.0, last_double_push.0);
+    if !position.legal_moves().is_empty() {
+        return;
+    }
+
+    let result = if position.is_in_check(turn.0) {
+        GameResult::Checkmate(turn.0.opponent())
+    } else {
+        GameResult::Stalemate
+    };
+
+    game_over.0 = Some(result);
+    game_over_event.send(GameOverEvent(result));
+}
+
+/// File the game is saved to as PGN
+const PGN_PATH: &str = "game.pgn";
+
+/// Save the game as PGN when it ends, or when P is pressed
+fn save_pgn(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut event_reader: EventReader<GameOverEvent>,
+    game_over: Res<GameOver>,
+    starting_position: Res<StartingPosition>,
+    history: Res<MoveHistory>,
+) {
+    let game_ended = event_reader.iter().count() > 0;
+    if !game_ended && !keyboard_input.just_pressed(KeyCode::P) {
+        return;
+    }
+
+    let tags = PgnTags {
+        result: game_over
+            .0
+            .map_or("*", |result| result.pgn_result())
+            .to_string(),
+        ..Default::default()
+    };
+    let moves: Vec<Move> = history
+        .moves
+        .iter()
+        .map(|played_move| played_move.mv)
+        .collect();
+    let pgn = write_pgn(&tags, &starting_position.0, &moves);
+
+    match std::fs::write(PGN_PATH, pgn) {
+        Ok(()) => println!("Game saved to {}", PGN_PATH),
+        Err(error) => eprintln!("Couldn't save the game to {}: {}", PGN_PATH, error),
+    }
+}
+
 pub struct BoardPlugin;
 impl Plugin for BoardPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<SelectedSquare>()
             .init_resource::<SelectedPiece>()
+            .init_resource::<KeyboardCursor>()
             .init_resource::<SquareMaterials>()
+            .init_resource::<SquareHighlights>()
             .init_resource::<PlayerTurn>()
+            .init_resource::<LastDoublePush>()
+            .init_resource::<MoveClocks>()
+            .init_resource::<MoveHistory>()
+            .init_resource::<PendingPromotion>()
             .add_event::<ResetSelectedEvent>()
+            .add_event::<MoveEvent>()
+            .init_resource::<GameOver>()
+            .add_event::<PromotionEvent>()
+            .add_event::<GameOverEvent>()
+            .add_event::<NewGameEvent>()
+            .add_event::<JumpToMoveEvent>()
             .add_startup_system(create_board)
+            .add_startup_system(setup_starting_position)
             .add_system(color_squares)
-            .add_system(select_square.label("select_square"))
-            .add_system(
-                // move_piece needs to run before select_piece
-                move_piece
-
-                    .after("select_square")
-                    .before("select_piece"),
-            )
-            .add_system(
-                select_piece
-
-                    .after("select_square")
-                    .label("select_piece"),
+            .add_system_to_stage(CoreStage::PostUpdate, update_square_highlights)
+            // The board only reacts while playing, not in the menus or while paused
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(select_square.label("select_square"))
+                    // Selects squares like select_square does, so it goes by the same label
+                    .with_system(move_keyboard_cursor.label("select_square"))
+                    .with_system(
+                        // move_piece needs to run before select_piece
+                        move_piece
+                            .label("move_piece")
+                            .after("select_square")
+                            .before("select_piece"),
+                    )
+                    .with_system(select_piece.after("select_square").label("select_piece"))
+                    .with_system(start_drag.after("select_piece"))
+                    .with_system(drag_piece)
+                    .with_system(drop_piece)
+                    .with_system(promote_pawn.label("promote_pawn"))
+                    .with_system(apply_move.after("move_piece").after("promote_pawn"))
+                    .with_system(undo_redo)
+                    .with_system(jump_to_move)
+                    .with_system(reset_selected.after("select_square")),
             )
+            .add_system(new_game)
             .add_system(despawn_taken_pieces)
-            .add_system(reset_selected.after("select_square"));
+            .add_system(show_captured_pieces)
+            .add_system_to_stage(CoreStage::PostUpdate, check_game_over)
+            .add_system(save_pgn);
     }
 }
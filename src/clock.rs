@@ -0,0 +1,212 @@
+use crate::{board::*, menu::AppState, pieces::*, replay::Replay};
+use bevy::prelude::*;
+use std::time::Duration;
+
+/// Time each side starts with, and what it gets back on every move
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeControl {
+    pub base: Duration,
+    /// Added to the clock of the side that just moved
+    pub increment: Duration,
+    /// Waited at the start of every turn before the clock starts running
+    pub delay: Duration,
+}
+impl TimeControl {
+    /// Reads "5+3" as 5 minutes with a 3 second increment, and "5d3" as 5 minutes
+    /// with a 3 second delay. "5" alone is 5 minutes for the whole game
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("{} is not a valid time control", text);
+        let seconds = |value: &str| -> Result<f32, String> {
+            match value.parse::<f32>() {
+                Ok(value) if value >= 0. && value.is_finite() => Ok(value),
+                _ => Err(invalid()),
+            }
+        };
+
+        let (base, increment, delay) = if let Some((base, increment)) = text.split_once('+') {
+            (base, seconds(increment)?, 0.)
+        } else if let Some((base, delay)) = text.split_once('d') {
+            (base, 0., seconds(delay)?)
+        } else {
+            (text, 0., 0.)
+        };
+
+        let base = seconds(base)? * 60.;
+        if base == 0. {
+            return Err(invalid());
+        }
+        // Duration::from_secs_f32 panics on times it can't hold
+        if [base, increment, delay]
+            .iter()
+            .any(|&seconds| seconds >= u64::MAX as f32)
+        {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            base: Duration::from_secs_f32(base),
+            increment: Duration::from_secs_f32(increment),
+            delay: Duration::from_secs_f32(delay),
+        })
+    }
+}
+
+/// Time left on each side's clock, only present when the game is played with a time control
+pub struct ChessClocks {
+    pub time_control: TimeControl,
+    pub white: Duration,
+    pub black: Duration,
+    /// Side whose clock is running
+    pub running: PieceColor,
+    /// Part of the delay that's still left this turn
+    delay_left: Duration,
+    /// Moves in the history when the turn started, to tell moves from undos
+    moves_played: usize,
+}
+impl ChessClocks {
+    pub fn new(time_control: TimeControl) -> Self {
+        Self {
+            time_control,
+            white: time_control.base,
+            black: time_control.base,
+            running: PieceColor::White,
+            delay_left: time_control.delay,
+            moves_played: 0,
+        }
+    }
+
+    pub fn time_left(&self, color: PieceColor) -> Duration {
+        match color {
+            PieceColor::White => self.white,
+            PieceColor::Black => self.black,
+        }
+    }
+
+    fn time_left_mut(&mut self, color: PieceColor) -> &mut Duration {
+        match color {
+            PieceColor::White => &mut self.white,
+            PieceColor::Black => &mut self.black,
+        }
+    }
+}
+
+/// Time as shown on the clock, "4:05", with tenths of a second under ten seconds, "9.3"
+pub fn format_clock(time: Duration) -> String {
+    if time < Duration::from_secs(10) {
+        format!("{}.{}", time.as_secs(), time.subsec_millis() / 100)
+    } else {
+        let seconds = time.as_secs();
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// Runs the clock of the side to move, switching clocks when the turn changes.
+/// The side whose clock reaches zero loses the game
+#[allow(clippy::too_many_arguments)]
+fn tick_clocks(
+    time: Res<Time>,
+    chess_clocks: Option<ResMut<ChessClocks>>,
+    turn: Res<PlayerTurn>,
+    history: Res<MoveHistory>,
+    mut game_over: ResMut<GameOver>,
+    mut game_over_event: EventWriter<GameOverEvent>,
+    replay: Option<Res<Replay>>,
+) {
+    let mut chess_clocks = match chess_clocks {
+        Some(chess_clocks) => chess_clocks,
+        None => return,
+    };
+    if game_over.0.is_some() || replay.is_some() {
+        return;
+    }
+
+    if chess_clocks.running != turn.0 {
+        // Moves earn the increment, taking them back doesn't
+        if history.moves.len() > chess_clocks.moves_played {
+            let increment = chess_clocks.time_control.increment;
+            let running = chess_clocks.running;
+            *chess_clocks.time_left_mut(running) += increment;
+        }
+        chess_clocks.running = turn.0;
+        chess_clocks.delay_left = chess_clocks.time_control.delay;
+        chess_clocks.moves_played = history.moves.len();
+    }
+
+    let mut elapsed = time.delta();
+    let delay = chess_clocks.delay_left.min(elapsed);
+    chess_clocks.delay_left -= delay;
+    elapsed -= delay;
+
+    let time_left = chess_clocks.time_left_mut(turn.0);
+    *time_left = time_left.saturating_sub(elapsed);
+
+    if time_left.is_zero() {
+        let result = GameResult::OutOfTime(turn.0.opponent());
+        game_over.0 = Some(result);
+        game_over_event.send(GameOverEvent(result));
+    }
+}
+
+/// Both sides get their full time back for a new game
+fn reset_clocks(
+    mut event_reader: EventReader<NewGameEvent>,
+    chess_clocks: Option<ResMut<ChessClocks>>,
+) {
+    if let (Some(_), Some(mut chess_clocks)) = (event_reader.iter().last(), chess_clocks) {
+        *chess_clocks = ChessClocks::new(chess_clocks.time_control);
+    }
+}
+
+pub struct ClockPlugin;
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut App) {
+        // Clocks stop in the menus and while the game is paused
+        app.add_system_set(SystemSet::on_update(AppState::Playing).with_system(tick_clocks))
+            .add_system(reset_clocks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_time_controls() {
+        assert_eq!(
+            TimeControl::parse("5+3"),
+            Ok(TimeControl {
+                base: Duration::from_secs(300),
+                increment: Duration::from_secs(3),
+                delay: Duration::ZERO,
+            })
+        );
+        assert_eq!(
+            TimeControl::parse("90d5"),
+            Ok(TimeControl {
+                base: Duration::from_secs(5400),
+                increment: Duration::ZERO,
+                delay: Duration::from_secs(5),
+            })
+        );
+        assert_eq!(
+            TimeControl::parse("0.5").map(|time_control| time_control.base),
+            Ok(Duration::from_secs(30))
+        );
+        assert!(TimeControl::parse("").is_err());
+        assert!(TimeControl::parse("0+2").is_err());
+        assert!(TimeControl::parse("5+x").is_err());
+        assert!(TimeControl::parse("-5").is_err());
+        assert!(TimeControl::parse("1e30").is_err());
+        assert!(TimeControl::parse("1e38").is_err());
+        assert!(TimeControl::parse("5+1e30").is_err());
+        assert!(TimeControl::parse("5d1e30").is_err());
+    }
+
+    #[test]
+    fn formats_clocks() {
+        assert_eq!(format_clock(Duration::from_secs(300)), "5:00");
+        assert_eq!(format_clock(Duration::from_secs(65)), "1:05");
+        assert_eq!(format_clock(Duration::from_millis(9_350)), "9.3");
+        assert_eq!(format_clock(Duration::ZERO), "0.0");
+    }
+}
@@ -0,0 +1,11 @@
+pub mod ai;
+pub mod board;
+pub mod clock;
+pub mod menu;
+pub mod pgn;
+pub mod pieces;
+pub mod replay;
+pub mod rules;
+pub mod search;
+pub mod uci;
+pub mod ui;
@@ -1,31 +1,107 @@
 use bevy::prelude::*;
+use bevy_chess::{
+    ai::*, board::*, clock::*, menu::*, pgn::read_pgn, pieces::*, replay::*, rules::Position,
+    uci::*, ui::*,
+};
 use bevy_mod_picking::*;
 
-mod pieces;
-use pieces::*;
-mod board;
-use board::*;
-mod ui;
-use ui::*;
-
 fn main() {
-    App::new()
+    let mut app = App::new();
+    app
         // Set antialiasing to use 4 samples
         .insert_resource(Msaa { samples: 4 })
         // Set WindowDescriptor Resource to change title and size
         .insert_resource(WindowDescriptor {
             title: "Chess!".to_string(),
-            width: 600.,
+            width: 800.,
             height: 600.,
             ..Default::default()
         })
         .add_plugins(DefaultPlugins)
         .add_plugins(DefaultPickingPlugins)
+        .add_plugin(MenuPlugin)
         .add_plugin(BoardPlugin)
         .add_plugin(PiecesPlugin)
         .add_plugin(UIPlugin)
-        .add_startup_system(setup)
-        .run();
+        .add_plugin(ReplayPlugin)
+        .add_plugin(AiPlugin)
+        .add_plugin(UciPlugin)
+        .add_plugin(ClockPlugin)
+        .add_startup_system(setup);
+    load_game_from_args(&mut app);
+    app.run();
+}
+
+/// Starts from the position given with `--fen "<FEN>"`,
+/// or replays the game in the file given with `--pgn <path>`.
+/// The computer plays the color given with `--ai <white|black>`,
+/// using the UCI engine given with `--engine <path>` instead of the built-in search.
+/// The game is played with clocks when a time control is given with `--clock`, like `--clock 5+3`
+fn load_game_from_args(app: &mut App) {
+    let args: Vec<String> = std::env::args().collect();
+    let argument = |name: &str| {
+        let index = args.iter().position(|arg| arg == name)?;
+        match args.get(index + 1) {
+            Some(value) => Some(value.clone()),
+            None => exit_with_error(&format!("{} needs a value", name)),
+        }
+    };
+
+    if let Some(fen) = argument("--fen") {
+        match Position::from_fen(&fen) {
+            Ok(position) => app.insert_resource(StartingPosition(position)),
+            Err(error) => exit_with_error(&format!("Invalid FEN: {}", error)),
+        };
+    }
+
+    if let Some(color) = argument("--ai") {
+        let color = match color.as_str() {
+            "white" => PieceColor::White,
+            "black" => PieceColor::Black,
+            _ => exit_with_error("--ai must be white or black"),
+        };
+        app.insert_resource(AiPlayer {
+            color: Some(color),
+            ..Default::default()
+        });
+    }
+
+    if let Some(path) = argument("--engine") {
+        match UciEngine::start(&path) {
+            Ok(engine) => app.insert_resource(EnginePlayer::new(engine)),
+            Err(error) => exit_with_error(&format!("Couldn't use the engine: {}", error)),
+        };
+        // The engine plays Black unless told otherwise
+        if argument("--ai").is_none() {
+            app.insert_resource(AiPlayer {
+                color: Some(PieceColor::Black),
+                ..Default::default()
+            });
+        }
+    }
+
+    if let Some(time_control) = argument("--clock") {
+        match TimeControl::parse(&time_control) {
+            Ok(time_control) => app.insert_resource(ChessClocks::new(time_control)),
+            Err(error) => exit_with_error(&error),
+        };
+    }
+
+    if let Some(path) = argument("--pgn") {
+        let pgn = std::fs::read_to_string(&path)
+            .unwrap_or_else(|error| exit_with_error(&format!("Couldn't read {}: {}", path, error)));
+        match read_pgn(&pgn) {
+            Ok(game) => app
+                .insert_resource(StartingPosition(game.starting_position.clone()))
+                .insert_resource(Replay::new(&game)),
+            Err(error) => exit_with_error(&format!("Invalid PGN in {}: {}", path, error)),
+        };
+    }
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
 }
 
 fn setup(mut commands: Commands) {
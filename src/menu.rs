@@ -0,0 +1,227 @@
+use crate::board::*;
+use bevy::{ecs::schedule::ShouldRun, prelude::*};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AppState {
+    MainMenu,
+    Playing,
+    /// Pushed on top of Playing, popping it goes back to the game
+    Paused,
+    GameOver,
+}
+
+/// Run criteria for the systems of other stages, where `SystemSet::on_update` can't be used
+pub fn playing(state: Res<State<AppState>>) -> ShouldRun {
+    if state.current() == &AppState::Playing {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+// Component to mark the root node of the menu shown in the current state
+#[derive(Component)]
+struct Menu;
+
+// Component for the menu buttons, with what they do
+#[derive(Component, Clone, Copy)]
+enum MenuButton {
+    NewGame,
+    Resume,
+    MainMenu,
+}
+
+/// Spawns a menu over the board, with a title and a column of buttons
+fn spawn_menu(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    title: &str,
+    buttons: &[(&str, MenuButton)],
+) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::rgba(0., 0., 0., 0.6).into(),
+            ..Default::default()
+        })
+        .insert(Menu)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                style: Style {
+                    margin: Rect::all(Val::Px(20.)),
+                    ..Default::default()
+                },
+                text: Text::with_section(
+                    title,
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 50.0,
+                        color: Color::rgb(0.8, 0.8, 0.8),
+                    },
+                    Default::default(),
+                ),
+                ..Default::default()
+            });
+
+            for (label, button) in buttons {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(220.), Val::Px(50.)),
+                            margin: Rect::all(Val::Px(5.)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        color: Color::rgb(0.15, 0.15, 0.15).into(),
+                        ..Default::default()
+                    })
+                    .insert(*button)
+                    .with_children(|parent| {
+                        parent.spawn_bundle(TextBundle {
+                            text: Text::with_section(
+                                *label,
+                                TextStyle {
+                                    font: font.clone(),
+                                    font_size: 30.0,
+                                    color: Color::rgb(0.8, 0.8, 0.8),
+                                },
+                                Default::default(),
+                            ),
+                            ..Default::default()
+                        });
+                    });
+            }
+        });
+}
+
+fn despawn_menu(mut commands: Commands, query: Query<Entity, With<Menu>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn main_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        "Chess!",
+        &[("New game", MenuButton::NewGame)],
+    );
+}
+
+fn pause_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        "Paused",
+        &[
+            ("Resume", MenuButton::Resume),
+            ("New game", MenuButton::NewGame),
+            ("Main menu", MenuButton::MainMenu),
+        ],
+    );
+}
+
+/// Shows the result, and offers to play again
+fn game_over_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    game_over: Res<GameOver>,
+) {
+    let title = game_over.0.map_or("Game over", |result| result.message());
+    spawn_menu(
+        &mut commands,
+        &asset_server,
+        title,
+        &[
+            ("Rematch", MenuButton::NewGame),
+            ("Main menu", MenuButton::MainMenu),
+        ],
+    );
+}
+
+fn menu_button_click(
+    mut state: ResMut<State<AppState>>,
+    mut query: Query<(&Interaction, &MenuButton, &mut UiColor), Changed<Interaction>>,
+    mut new_game_event: EventWriter<NewGameEvent>,
+) {
+    for (interaction, button, mut color) in query.iter_mut() {
+        match interaction {
+            Interaction::Clicked => {
+                let _ = match button {
+                    MenuButton::NewGame => {
+                        new_game_event.send(NewGameEvent);
+                        state.replace(AppState::Playing)
+                    }
+                    MenuButton::Resume => state.pop(),
+                    MenuButton::MainMenu => state.replace(AppState::MainMenu),
+                };
+            }
+            Interaction::Hovered => *color = Color::rgb(0.25, 0.25, 0.25).into(),
+            Interaction::None => *color = Color::rgb(0.15, 0.15, 0.15).into(),
+        }
+    }
+}
+
+/// Escape pauses the game, and resumes it once paused
+fn toggle_pause(keyboard_input: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+    if !keyboard_input.just_pressed(KeyCode::Escape) {
+        return;
+    }
+
+    let _ = match state.current() {
+        AppState::Playing => state.push(AppState::Paused),
+        AppState::Paused => state.pop(),
+        _ => return,
+    };
+}
+
+/// N starts a new game, from the game, the pause menu or the game over screen
+fn new_game_on_key(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut state: ResMut<State<AppState>>,
+    mut new_game_event: EventWriter<NewGameEvent>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::N) || state.current() == &AppState::MainMenu {
+        return;
+    }
+
+    new_game_event.send(NewGameEvent);
+    let _ = state.replace(AppState::Playing);
+}
+
+fn show_game_over(
+    mut event_reader: EventReader<GameOverEvent>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if event_reader.iter().last().is_some() && state.current() == &AppState::Playing {
+        let _ = state.set(AppState::GameOver);
+    }
+}
+
+pub struct MenuPlugin;
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state(AppState::MainMenu)
+            .add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(main_menu))
+            .add_system_set(SystemSet::on_exit(AppState::MainMenu).with_system(despawn_menu))
+            .add_system_set(SystemSet::on_enter(AppState::Paused).with_system(pause_menu))
+            .add_system_set(SystemSet::on_exit(AppState::Paused).with_system(despawn_menu))
+            .add_system_set(SystemSet::on_enter(AppState::GameOver).with_system(game_over_screen))
+            .add_system_set(SystemSet::on_exit(AppState::GameOver).with_system(despawn_menu))
+            .add_system(menu_button_click)
+            .add_system(toggle_pause)
+            .add_system(new_game_on_key)
+            .add_system(show_game_over);
+    }
+}
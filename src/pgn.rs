@@ -0,0 +1,421 @@
+use crate::{
+    pieces::PieceColor,
+    rules::{Move, Position, STARTING_FEN},
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The Seven Tag Roster, the tags every PGN game starts with
+pub struct PgnTags {
+    pub event: String,
+    pub site: String,
+    pub date: String,
+    pub round: String,
+    pub white: String,
+    pub black: String,
+    /// "1-0", "0-1", "1/2-1/2", or "*" while the game is still being played
+    pub result: String,
+}
+impl Default for PgnTags {
+    fn default() -> Self {
+        Self {
+            event: "Casual game".to_string(),
+            site: "Bevy Chess".to_string(),
+            date: today(),
+            round: "-".to_string(),
+            white: "?".to_string(),
+            black: "?".to_string(),
+            result: "*".to_string(),
+        }
+    }
+}
+
+/// Writes the moves played from `starting_position` as a PGN game, with the moves in SAN
+pub fn write_pgn(tags: &PgnTags, starting_position: &Position, moves: &[Move]) -> String {
+    let mut pgn = String::new();
+    for (name, value) in [
+        ("Event", &tags.event),
+        ("Site", &tags.site),
+        ("Date", &tags.date),
+        ("Round", &tags.round),
+        ("White", &tags.white),
+        ("Black", &tags.black),
+        ("Result", &tags.result),
+    ] {
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, escape(value)));
+    }
+
+    // Games that don't start from the usual position need to say where they start
+    let fen = starting_position.to_fen();
+    if fen != STARTING_FEN {
+        pgn.push_str("[SetUp \"1\"]\n");
+        pgn.push_str(&format!("[FEN \"{}\"]\n", fen));
+    }
+    pgn.push('\n');
+
+    let mut tokens = Vec::new();
+    let mut position = starting_position.clone();
+    for (i, mv) in moves.iter().enumerate() {
+        match position.turn {
+            PieceColor::White => tokens.push(format!("{}.", position.fullmove_number)),
+            // Black's moves only get a number when they start the movetext
+            PieceColor::Black if i == 0 => tokens.push(format!("{}...", position.fullmove_number)),
+            PieceColor::Black => {}
+        }
+        tokens.push(position.san(*mv));
+        position.make_move(*mv);
+    }
+    tokens.push(tags.result.clone());
+
+    // Keep lines under 80 characters
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > 79 {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    pgn.push_str(&line);
+    pgn.push('\n');
+
+    pgn
+}
+
+/// A game read from a PGN file
+pub struct PgnGame {
+    pub tags: PgnTags,
+    pub starting_position: Position,
+    pub moves: Vec<Move>,
+}
+
+/// Reads the first game of a PGN file, checking every move against the rules
+pub fn read_pgn(pgn: &str) -> Result<PgnGame, String> {
+    let mut tags = PgnTags {
+        event: "?".to_string(),
+        site: "?".to_string(),
+        date: "????.??.??".to_string(),
+        round: "?".to_string(),
+        ..Default::default()
+    };
+    let mut fen = None;
+    let mut movetext = String::new();
+
+    for line in pgn.lines() {
+        let line = line.trim();
+        if let Some(tag) = line.strip_prefix('[') {
+            // A tag after the moves is the start of the next game
+            if !movetext.trim().is_empty() {
+                break;
+            }
+
+            let tag = tag.trim_end_matches(']');
+            let (name, value) = match tag.split_once(' ') {
+                Some((name, value)) => {
+                    let value = value.trim();
+                    let value = value.strip_prefix('"').unwrap_or(value);
+                    let value = value.strip_suffix('"').unwrap_or(value);
+                    (name, unescape(value))
+                }
+                None => return Err(format!("invalid tag [{}]", tag)),
+            };
+            match name {
+                "Event" => tags.event = value,
+                "Site" => tags.site = value,
+                "Date" => tags.date = value,
+                "Round" => tags.round = value,
+                "White" => tags.white = value,
+                "Black" => tags.black = value,
+                "Result" => tags.result = value,
+                "FEN" => fen = Some(value),
+                _ => {}
+            }
+        } else if !line.starts_with('%') {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+
+    let starting_position = Position::from_fen(fen.as_deref().unwrap_or(STARTING_FEN))?;
+    let mut position = starting_position.clone();
+    let mut moves = Vec::new();
+
+    for token in movetext_tokens(&movetext) {
+        if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token.as_str()) {
+            break;
+        }
+        if token.starts_with('$') {
+            continue;
+        }
+
+        // Move numbers can be stuck to the move, like "1.e4" or "12...Nf6",
+        // but the digits of castling written with zeros aren't one
+        let numbered = token.trim_start_matches(|c: char| c.is_ascii_digit());
+        let san = if numbered.starts_with('.') {
+            numbered.trim_start_matches('.')
+        } else {
+            token.as_str()
+        };
+        if san.is_empty() {
+            continue;
+        }
+
+        let mv = position
+            .parse_san(san)
+            .map_err(|error| format!("move {}: {}", moves.len() + 1, error))?;
+        position.make_move(mv);
+        moves.push(mv);
+    }
+
+    Ok(PgnGame {
+        tags,
+        starting_position,
+        moves,
+    })
+}
+
+/// Splits the moves into tokens, leaving out comments and variations
+fn movetext_tokens(movetext: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut variation_depth = 0;
+    let mut chars = movetext.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                // Comments end at the next closing brace
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                }
+            }
+            ';' => {
+                // And these ones at the end of the line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' => variation_depth += 1,
+            ')' => variation_depth -= 1,
+            _ if variation_depth > 0 => {}
+            _ if c.is_whitespace() => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                continue;
+            }
+            _ => {
+                token.push(c);
+                continue;
+            }
+        }
+
+        // Comments and variations also separate tokens
+        if !token.is_empty() {
+            tokens.push(std::mem::take(&mut token));
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("\\\"", "\"").replace("\\\\", "\\")
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Today's date in the "YYYY.MM.DD" format used by the Date tag
+fn today() -> String {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => civil_date((duration.as_secs() / 86400) as i64),
+        Err(_) => "????.??.??".to_string(),
+    }
+}
+
+/// Days since 1970-01-01 to a "YYYY.MM.DD" date,
+/// from http://howardhinnant.github.io/date_algorithms.html
+fn civil_date(days_since_epoch: i64) -> String {
+    let days = days_since_epoch + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::parse_square;
+
+    fn moves(position: &Position, squares: &[(&str, &str)]) -> Vec<Move> {
+        let mut position = position.clone();
+        squares
+            .iter()
+            .map(|(from, to)| {
+                let mv = Move {
+                    from: parse_square(from).unwrap(),
+                    to: parse_square(to).unwrap(),
+                    promotion: None,
+                };
+                position.make_move(mv);
+                mv
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_fools_mate() {
+        let position = Position::from_fen(STARTING_FEN).unwrap();
+        let moves = moves(
+            &position,
+            &[("f2", "f3"), ("e7", "e5"), ("g2", "g4"), ("d8", "h4")],
+        );
+        let tags = PgnTags {
+            date: "2022.05.01".to_string(),
+            result: "0-1".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            write_pgn(&tags, &position, &moves),
+            "[Event \"Casual game\"]\n\
+             [Site \"Bevy Chess\"]\n\
+             [Date \"2022.05.01\"]\n\
+             [Round \"-\"]\n\
+             [White \"?\"]\n\
+             [Black \"?\"]\n\
+             [Result \"0-1\"]\n\
+             \n\
+             1. f3 e5 2. g4 Qh4# 0-1\n"
+        );
+    }
+
+    #[test]
+    fn writes_setup_when_black_starts() {
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 12";
+        let position = Position::from_fen(fen).unwrap();
+        let moves = moves(&position, &[("e8", "d7"), ("e2", "e4")]);
+        let pgn = write_pgn(&PgnTags::default(), &position, &moves);
+
+        assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 12\"]\n"));
+        assert!(pgn.ends_with("\n12... Kd7 13. e4 *\n"));
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let position = Position::from_fen(fen).unwrap();
+        let moves = moves(
+            &position,
+            &[("e1", "g1"), ("e8", "c8"), ("d5", "e6"), ("h3", "g2")],
+        );
+        let tags = PgnTags {
+            white: "Someone \"quoted\"".to_string(),
+            ..Default::default()
+        };
+
+        let game = read_pgn(&write_pgn(&tags, &position, &moves)).unwrap();
+        assert_eq!(game.starting_position, position);
+        assert_eq!(game.moves, moves);
+        assert_eq!(game.tags.white, tags.white);
+        assert_eq!(game.tags.result, "*");
+    }
+
+    #[test]
+    fn reads_comments_and_variations() {
+        let pgn = "[Event \"Test\"]\n\
+                   [Result \"1-0\"]\n\
+                   \n\
+                   1.e4 {best by test} e5 (1...c5 2.Nf3 (2.c3)) 2. Nf3 $1 ; a comment\n\
+                   2... Nc6 3.Bb5!? 1-0\n\
+                   \n\
+                   [Event \"Next game\"]\n\
+                   \n\
+                   1. d4 *\n";
+        let game = read_pgn(pgn).unwrap();
+
+        assert_eq!(game.tags.event, "Test");
+        assert_eq!(game.tags.result, "1-0");
+        let position = Position::from_fen(STARTING_FEN).unwrap();
+        assert_eq!(
+            game.moves,
+            moves(
+                &position,
+                &[
+                    ("e2", "e4"),
+                    ("e7", "e5"),
+                    ("g1", "f3"),
+                    ("b8", "c6"),
+                    ("f1", "b5")
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn reads_castling_written_with_zeros() {
+        let pgn = "1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4.0-0 d6 5. d3 Bg4 6. Nc3 Qd7 7. Be3 0-0-0 *";
+        let game = read_pgn(pgn).unwrap();
+
+        assert_eq!(game.moves.len(), 14);
+        let king_move = |from, to| Move {
+            from,
+            to,
+            promotion: None,
+        };
+        assert_eq!(game.moves[6], king_move((0, 4), (0, 6)));
+        assert_eq!(game.moves[13], king_move((7, 4), (7, 2)));
+    }
+
+    #[test]
+    fn reports_illegal_moves() {
+        assert_eq!(
+            read_pgn("1. e4 e5 2. Ke3 *").err(),
+            Some("move 3: Ke3 is not a legal move".to_string())
+        );
+    }
+
+    #[test]
+    fn date_has_the_tag_format() {
+        let date = today();
+        assert_eq!(date.len(), 10);
+        assert_eq!(&date[4..5], ".");
+        assert_eq!(&date[7..8], ".");
+    }
+
+    #[test]
+    fn days_become_civil_dates() {
+        assert_eq!(civil_date(0), "1970.01.01");
+        assert_eq!(civil_date(58), "1970.02.28");
+        assert_eq!(civil_date(59), "1970.03.01");
+        assert_eq!(civil_date(365), "1971.01.01");
+        assert_eq!(civil_date(11016), "2000.02.29");
+        assert_eq!(civil_date(11017), "2000.03.01");
+        assert_eq!(civil_date(19782), "2024.02.29");
+        assert_eq!(civil_date(20088), "2024.12.31");
+    }
+}
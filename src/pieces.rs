@@ -1,12 +1,13 @@
+use crate::rules::{Position, STARTING_FEN};
 use bevy::prelude::*;
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum PieceColor {
     White,
     Black,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum PieceType {
     King,
     Queen,
@@ -15,6 +16,18 @@ pub enum PieceType {
     Rook,
     Pawn,
 }
+impl PieceType {
+    /// Value of the piece in the usual 1, 3, 3, 5, 9 points, kings can't be traded
+    pub fn points(self) -> i32 {
+        match self {
+            PieceType::Pawn => 1,
+            PieceType::Knight | PieceType::Bishop => 3,
+            PieceType::Rook => 5,
+            PieceType::Queen => 9,
+            PieceType::King => 0,
+        }
+    }
+}
 
 #[derive(Clone, Copy, Component)]
 pub struct Piece {
@@ -23,354 +36,154 @@ pub struct Piece {
     // Current position
     pub x: u8,
     pub y: u8,
+    // Needed for castling, kings and rooks lose the right once they move
+    pub has_moved: bool,
 }
 impl Piece {
-    /// Returns the possible_positions that are available
-    pub fn is_move_valid(&self, new_position: (u8, u8), pieces: Vec<Piece>) -> bool {
-        // If there's a piece of the same color in the same square, it can't move
-        if color_of_square(new_position, &pieces) == Some(self.color) {
-            return false;
-        }
-
-        match self.piece_type {
-            PieceType::King => {
-                // Horizontal
-                ((self.x as i8 - new_position.0 as i8).abs() == 1
-                    && (self.y == new_position.1))
-                // Vertical
-                || ((self.y as i8 - new_position.1 as i8).abs() == 1
-                    && (self.x == new_position.0))
-                // Diagonal
-                || ((self.x as i8 - new_position.0 as i8).abs() == 1
-                    && (self.y as i8 - new_position.1 as i8).abs() == 1)
-            }
-            PieceType::Queen => {
-                is_path_empty((self.x, self.y), new_position, &pieces)
-                    && ((self.x as i8 - new_position.0 as i8).abs()
-                        == (self.y as i8 - new_position.1 as i8).abs()
-                        || ((self.x == new_position.0 && self.y != new_position.1)
-                            || (self.y == new_position.1 && self.x != new_position.0)))
-            }
-            PieceType::Bishop => {
-                is_path_empty((self.x, self.y), new_position, &pieces)
-                    && (self.x as i8 - new_position.0 as i8).abs()
-                        == (self.y as i8 - new_position.1 as i8).abs()
-            }
-            PieceType::Knight => {
-                ((self.x as i8 - new_position.0 as i8).abs() == 2
-                    && (self.y as i8 - new_position.1 as i8).abs() == 1)
-                    || ((self.x as i8 - new_position.0 as i8).abs() == 1
-                        && (self.y as i8 - new_position.1 as i8).abs() == 2)
-            }
-            PieceType::Rook => {
-                is_path_empty((self.x, self.y), new_position, &pieces)
-                    && ((self.x == new_position.0 && self.y != new_position.1)
-                        || (self.y == new_position.1 && self.x != new_position.0))
-            }
-            PieceType::Pawn => {
-                if self.color == PieceColor::White {
-                    // Normal move
-                    if new_position.0 as i8 - self.x as i8 == 1 && (self.y == new_position.1) {
-                        if color_of_square(new_position, &pieces).is_none() {
-                            return true;
-                        }
-                    }
-
-                    // Move 2 squares
-                    if self.x == 1
-                        && new_position.0 as i8 - self.x as i8 == 2
-                        && (self.y == new_position.1)
-                        && is_path_empty((self.x, self.y), new_position, &pieces)
-                    {
-                        if color_of_square(new_position, &pieces).is_none() {
-                            return true;
-                        }
-                    }
-
-                    // Take piece
-                    if new_position.0 as i8 - self.x as i8 == 1
-                        && (self.y as i8 - new_position.1 as i8).abs() == 1
-                    {
-                        if color_of_square(new_position, &pieces) == Some(PieceColor::Black) {
-                            return true;
-                        }
-                    }
-                } else {
-                    // Normal move
-                    if new_position.0 as i8 - self.x as i8 == -1 && (self.y == new_position.1) {
-                        if color_of_square(new_position, &pieces).is_none() {
-                            return true;
-                        }
-                    }
-
-                    // Move 2 squares
-                    if self.x == 6
-                        && new_position.0 as i8 - self.x as i8 == -2
-                        && (self.y == new_position.1)
-                        && is_path_empty((self.x, self.y), new_position, &pieces)
-                    {
-                        if color_of_square(new_position, &pieces).is_none() {
-                            return true;
-                        }
-                    }
-
-                    // Take piece
-                    if new_position.0 as i8 - self.x as i8 == -1
-                        && (self.y as i8 - new_position.1 as i8).abs() == 1
-                    {
-                        if color_of_square(new_position, &pieces) == Some(PieceColor::White) {
-                            return true;
-                        }
-                    }
-                }
-
-                false
-            }
-        }
+    /// Returns true if the piece can legally move to `new_position`
+    pub fn is_move_valid(&self, new_position: (u8, u8), position: &Position) -> bool {
+        position
+            .legal_moves_from((self.x, self.y))
+            .iter()
+            .any(|mv| mv.to == new_position)
     }
 }
 
-fn is_path_empty(begin: (u8, u8), end: (u8, u8), pieces: &Vec<Piece>) -> bool {
-    // Same column
-    if begin.0 == end.0 {
-        for piece in pieces {
-            if piece.x == begin.0
-                && ((piece.y > begin.1 && piece.y < end.1)
-                    || (piece.y > end.1 && piece.y < begin.1))
-            {
-                return false;
-            }
-        }
-    }
-    // Same row
-    if begin.1 == end.1 {
-        for piece in pieces {
-            if piece.y == begin.1
-                && ((piece.x > begin.0 && piece.x < end.0)
-                    || (piece.x > end.0 && piece.x < begin.0))
-            {
-                return false;
-            }
-        }
-    }
+/// Marks the piece held with the mouse, it follows the cursor instead of going to its square
+#[derive(Component)]
+pub struct Dragged;
 
-    // Diagonals
-    let x_diff = (begin.0 as i8 - end.0 as i8).abs();
-    let y_diff = (begin.1 as i8 - end.1 as i8).abs();
-    if x_diff == y_diff {
-        for i in 1..x_diff {
-            let pos = if begin.0 < end.0 && begin.1 < end.1 {
-                // left bottom - right top
-                (begin.0 + i as u8, begin.1 + i as u8)
-            } else if begin.0 < end.0 && begin.1 > end.1 {
-                // left top - right bottom
-                (begin.0 + i as u8, begin.1 - i as u8)
-            } else if begin.0 > end.0 && begin.1 < end.1 {
-                // right bottom - left top
-                (begin.0 - i as u8, begin.1 + i as u8)
-            } else {
-                // begin.0 > end.0 && begin.1 > end.1
-                // right top - left bottom
-                (begin.0 - i as u8, begin.1 - i as u8)
-            };
+fn move_pieces(time: Res<Time>, mut query: Query<(&mut Transform, &Piece), Without<Dragged>>) {
+    for (mut transform, piece) in query.iter_mut() {
+        // Get the direction to move in
+        let direction = Vec3::new(piece.x as f32, 0., piece.y as f32) - transform.translation;
 
-            if color_of_square(pos, pieces).is_some() {
-                return false;
-            }
+        // Only move if the piece isn't already there (distance is big)
+        if direction.length() > 0.1 {
+            transform.translation += direction.normalize() * time.delta_seconds();
         }
     }
+}
 
-    true
+/// Meshes and materials shared by all the pieces
+pub struct PieceMeshes {
+    king: Handle<Mesh>,
+    king_cross: Handle<Mesh>,
+    pawn: Handle<Mesh>,
+    knight_1: Handle<Mesh>,
+    knight_2: Handle<Mesh>,
+    rook: Handle<Mesh>,
+    bishop: Handle<Mesh>,
+    queen: Handle<Mesh>,
+    white_material: Handle<StandardMaterial>,
+    black_material: Handle<StandardMaterial>,
 }
 
-/// Returns None if square is empty, returns a Some with the color if not
-fn color_of_square(pos: (u8, u8), pieces: &Vec<Piece>) -> Option<PieceColor> {
-    for piece in pieces {
-        if piece.x == pos.0 && piece.y == pos.1 {
-            return Some(piece.color);
+impl FromWorld for PieceMeshes {
+    fn from_world(world: &mut World) -> Self {
+        let world = world.cell();
+        let asset_server = world.get_resource::<AssetServer>().unwrap();
+        let mut materials = world
+            .get_resource_mut::<Assets<StandardMaterial>>()
+            .unwrap();
+        PieceMeshes {
+            // Load all the meshes
+            king: asset_server.load("models/chess_kit/pieces.glb#Mesh0/Primitive0"),
+            king_cross: asset_server.load("models/chess_kit/pieces.glb#Mesh1/Primitive0"),
+            pawn: asset_server.load("models/chess_kit/pieces.glb#Mesh2/Primitive0"),
+            knight_1: asset_server.load("models/chess_kit/pieces.glb#Mesh3/Primitive0"),
+            knight_2: asset_server.load("models/chess_kit/pieces.glb#Mesh4/Primitive0"),
+            rook: asset_server.load("models/chess_kit/pieces.glb#Mesh5/Primitive0"),
+            bishop: asset_server.load("models/chess_kit/pieces.glb#Mesh6/Primitive0"),
+            queen: asset_server.load("models/chess_kit/pieces.glb#Mesh7/Primitive0"),
+            // Add some materials
+            white_material: materials.add(Color::rgb(1., 0.8, 0.8).into()),
+            black_material: materials.add(Color::rgb(0.3, 0.3, 0.3).into()),
         }
     }
-    None
 }
 
-fn move_pieces(time: Res<Time>, mut query: Query<(&mut Transform, &Piece)>) {
-    for (mut transform, piece) in query.iter_mut() {
-        // Get the direction to move in
-        let direction = Vec3::new(piece.x as f32, 0., piece.y as f32) - transform.translation;
-
-        // Only move if the piece isn't already there (distance is big)
-        if direction.length() > 0.1 {
-            transform.translation += direction.normalize() * time.delta_seconds();
-        }
+/// Position the game starts from, the standard one unless a FEN is given on the command line
+pub struct StartingPosition(pub Position);
+impl Default for StartingPosition {
+    fn default() -> Self {
+        Self(Position::from_fen(STARTING_FEN).unwrap())
     }
 }
 
 fn create_pieces(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    meshes: Res<PieceMeshes>,
+    starting_position: Res<StartingPosition>,
 ) {
-    // Load all the meshes
-    let king_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh0/Primitive0");
-    let king_cross_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh1/Primitive0");
-    let pawn_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh2/Primitive0");
-    let knight_1_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh3/Primitive0");
-    let knight_2_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh4/Primitive0");
-    let rook_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh5/Primitive0");
-    let bishop_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh6/Primitive0");
-    let queen_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh7/Primitive0");
-
-    // Add some materials
-    let white_material = materials.add(Color::rgb(1., 0.8, 0.8).into());
-    let black_material = materials.add(Color::rgb(0.3, 0.3, 0.3).into());
-
-    spawn_rook(
-        &mut commands,
-        white_material.clone(),
-        PieceColor::White,
-        rook_handle.clone(),
-        (0, 0),
-    );
-    spawn_knight(
-        &mut commands,
-        white_material.clone(),
-        PieceColor::White,
-        knight_1_handle.clone(),
-        knight_2_handle.clone(),
-        (0, 1),
-    );
-    spawn_bishop(
-        &mut commands,
-        white_material.clone(),
-        PieceColor::White,
-        bishop_handle.clone(),
-        (0, 2),
-    );
-    spawn_queen(
-        &mut commands,
-        white_material.clone(),
-        PieceColor::White,
-        queen_handle.clone(),
-        (0, 3),
-    );
-    spawn_king(
-        &mut commands,
-        white_material.clone(),
-        PieceColor::White,
-        king_handle.clone(),
-        king_cross_handle.clone(),
-        (0, 4),
-    );
-    spawn_bishop(
-        &mut commands,
-        white_material.clone(),
-        PieceColor::White,
-        bishop_handle.clone(),
-        (0, 5),
-    );
-    spawn_knight(
-        &mut commands,
-        white_material.clone(),
-        PieceColor::White,
-        knight_1_handle.clone(),
-        knight_2_handle.clone(),
-        (0, 6),
-    );
-    spawn_rook(
-        &mut commands,
-        white_material.clone(),
-        PieceColor::White,
-        rook_handle.clone(),
-        (0, 7),
-    );
+    spawn_pieces(&mut commands, &meshes, &starting_position.0);
+}
 
-    for i in 0..8 {
-        spawn_pawn(
-            &mut commands,
-            white_material.clone(),
-            PieceColor::White,
-            pawn_handle.clone(),
-            (1, i),
-        );
+/// Spawns every piece of `position`
+pub fn spawn_pieces(commands: &mut Commands, meshes: &PieceMeshes, position: &Position) {
+    for piece in position.to_pieces() {
+        spawn_piece(commands, meshes, piece);
     }
+}
 
-    spawn_rook(
-        &mut commands,
-        black_material.clone(),
-        PieceColor::Black,
-        rook_handle.clone(),
-        (7, 0),
-    );
-    spawn_knight(
-        &mut commands,
-        black_material.clone(),
-        PieceColor::Black,
-        knight_1_handle.clone(),
-        knight_2_handle.clone(),
-        (7, 1),
-    );
-    spawn_bishop(
-        &mut commands,
-        black_material.clone(),
-        PieceColor::Black,
-        bishop_handle.clone(),
-        (7, 2),
-    );
-    spawn_queen(
-        &mut commands,
-        black_material.clone(),
-        PieceColor::Black,
-        queen_handle.clone(),
-        (7, 3),
-    );
-    spawn_king(
-        &mut commands,
-        black_material.clone(),
-        PieceColor::Black,
-        king_handle.clone(),
-        king_cross_handle.clone(),
-        (7, 4),
-    );
-    spawn_bishop(
-        &mut commands,
-        black_material.clone(),
-        PieceColor::Black,
-        bishop_handle.clone(),
-        (7, 5),
-    );
-    spawn_knight(
-        &mut commands,
-        black_material.clone(),
-        PieceColor::Black,
-        knight_1_handle.clone(),
-        knight_2_handle.clone(),
-        (7, 6),
-    );
-    spawn_rook(
-        &mut commands,
-        black_material.clone(),
-        PieceColor::Black,
-        rook_handle.clone(),
-        (7, 7),
-    );
+/// Spawns a piece using the meshes and material from `PieceMeshes`
+pub fn spawn_piece(commands: &mut Commands, meshes: &PieceMeshes, piece: Piece) -> Entity {
+    let material = match piece.color {
+        PieceColor::White => meshes.white_material.clone(),
+        PieceColor::Black => meshes.black_material.clone(),
+    };
+    let position = (piece.x, piece.y);
 
-    for i in 0..8 {
-        spawn_pawn(
-            &mut commands,
-            black_material.clone(),
-            PieceColor::Black,
-            pawn_handle.clone(),
-            (6, i),
-        );
-    }
+    let entity = match piece.piece_type {
+        PieceType::King => spawn_king(
+            commands,
+            material,
+            piece.color,
+            meshes.king.clone(),
+            meshes.king_cross.clone(),
+            position,
+        ),
+        PieceType::Queen => spawn_queen(
+            commands,
+            material,
+            piece.color,
+            meshes.queen.clone(),
+            position,
+        ),
+        PieceType::Bishop => spawn_bishop(
+            commands,
+            material,
+            piece.color,
+            meshes.bishop.clone(),
+            position,
+        ),
+        PieceType::Knight => spawn_knight(
+            commands,
+            material,
+            piece.color,
+            meshes.knight_1.clone(),
+            meshes.knight_2.clone(),
+            position,
+        ),
+        PieceType::Rook => spawn_rook(
+            commands,
+            material,
+            piece.color,
+            meshes.rook.clone(),
+            position,
+        ),
+        PieceType::Pawn => spawn_pawn(
+            commands,
+            material,
+            piece.color,
+            meshes.pawn.clone(),
+            position,
+        ),
+    };
+
+    // The helpers spawn pieces that haven't moved, keep the original value
+    commands.entity(entity).insert(piece);
+    entity
 }
 
 fn spawn_king(
@@ -380,7 +193,7 @@ fn spawn_king(
     mesh: Handle<Mesh>,
     mesh_cross: Handle<Mesh>,
     position: (u8, u8),
-) {
+) -> Entity {
     commands
         // Spawn parent entity
         .spawn_bundle(PbrBundle {
@@ -396,6 +209,7 @@ fn spawn_king(
             piece_type: PieceType::King,
             x: position.0,
             y: position.1,
+            has_moved: false,
         })
         // Add children to the parent
         .with_children(|parent| {
@@ -419,7 +233,8 @@ fn spawn_king(
                 },
                 ..Default::default()
             });
-        });
+        })
+        .id()
 }
 
 fn spawn_knight(
@@ -429,7 +244,7 @@ fn spawn_knight(
     mesh_1: Handle<Mesh>,
     mesh_2: Handle<Mesh>,
     position: (u8, u8),
-) {
+) -> Entity {
     commands
         // Spawn parent entity
         .spawn_bundle(PbrBundle {
@@ -445,6 +260,7 @@ fn spawn_knight(
             piece_type: PieceType::Knight,
             x: position.0,
             y: position.1,
+            has_moved: false,
         })
         // Add children to the parent
         .with_children(|parent| {
@@ -468,7 +284,8 @@ fn spawn_knight(
                 },
                 ..Default::default()
             });
-        });
+        })
+        .id()
 }
 
 fn spawn_queen(
@@ -477,7 +294,7 @@ fn spawn_queen(
     piece_color: PieceColor,
     mesh: Handle<Mesh>,
     position: (u8, u8),
-) {
+) -> Entity {
     commands
         // Spawn parent entity
         .spawn_bundle(PbrBundle {
@@ -493,6 +310,7 @@ fn spawn_queen(
             piece_type: PieceType::Queen,
             x: position.0,
             y: position.1,
+            has_moved: false,
         })
         .with_children(|parent| {
             parent.spawn_bundle(PbrBundle {
@@ -505,7 +323,8 @@ fn spawn_queen(
                 },
                 ..Default::default()
             });
-        });
+        })
+        .id()
 }
 
 fn spawn_bishop(
@@ -514,7 +333,7 @@ fn spawn_bishop(
     piece_color: PieceColor,
     mesh: Handle<Mesh>,
     position: (u8, u8),
-) {
+) -> Entity {
     commands
         // Spawn parent entity
         .spawn_bundle(PbrBundle {
@@ -530,6 +349,7 @@ fn spawn_bishop(
             piece_type: PieceType::Bishop,
             x: position.0,
             y: position.1,
+            has_moved: false,
         })
         .with_children(|parent| {
             parent.spawn_bundle(PbrBundle {
@@ -542,7 +362,8 @@ fn spawn_bishop(
                 },
                 ..Default::default()
             });
-        });
+        })
+        .id()
 }
 
 fn spawn_rook(
@@ -551,7 +372,7 @@ fn spawn_rook(
     piece_color: PieceColor,
     mesh: Handle<Mesh>,
     position: (u8, u8),
-) {
+) -> Entity {
     commands
         // Spawn parent entity
         .spawn_bundle(PbrBundle {
@@ -567,6 +388,7 @@ fn spawn_rook(
             piece_type: PieceType::Rook,
             x: position.0,
             y: position.1,
+            has_moved: false,
         })
         .with_children(|parent| {
             parent.spawn_bundle(PbrBundle {
@@ -579,7 +401,8 @@ fn spawn_rook(
                 },
                 ..Default::default()
             });
-        });
+        })
+        .id()
 }
 
 fn spawn_pawn(
@@ -588,7 +411,7 @@ fn spawn_pawn(
     piece_color: PieceColor,
     mesh: Handle<Mesh>,
     position: (u8, u8),
-) {
+) -> Entity {
     commands
         // Spawn parent entity
         .spawn_bundle(PbrBundle {
@@ -604,6 +427,7 @@ fn spawn_pawn(
             piece_type: PieceType::Pawn,
             x: position.0,
             y: position.1,
+            has_moved: false,
         })
         .with_children(|parent| {
             parent.spawn_bundle(PbrBundle {
@@ -616,13 +440,16 @@ fn spawn_pawn(
                 },
                 ..Default::default()
             });
-        });
+        })
+        .id()
 }
 
 pub struct PiecesPlugin;
 impl Plugin for PiecesPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(create_pieces)
+        app.init_resource::<PieceMeshes>()
+            .init_resource::<StartingPosition>()
+            .add_startup_system(create_pieces)
             .add_system(move_pieces);
     }
 }
@@ -0,0 +1,94 @@
+use crate::{board::*, menu::AppState, pgn::PgnGame, pieces::*, rules::Position};
+use bevy::prelude::*;
+
+/// Game loaded from a PGN file, stepped through with the arrow keys.
+/// The board can't be played on while it exists
+pub struct Replay {
+    moves: Vec<PlayedMove>,
+    /// Position before each move, followed by the final one
+    positions: Vec<Position>,
+    /// Number of moves shown on the board
+    current: usize,
+}
+impl Replay {
+    pub fn new(game: &PgnGame) -> Self {
+        let mut positions = vec![game.starting_position.clone()];
+        for mv in game.moves.iter() {
+            let mut position = positions[positions.len() - 1].clone();
+            position.make_move(*mv);
+            positions.push(position);
+        }
+
+        Self {
+            moves: game
+                .moves
+                .iter()
+                .zip(positions.iter())
+                .map(|(mv, position)| PlayedMove::new(position, *mv))
+                .collect(),
+            positions,
+            current: 0,
+        }
+    }
+
+    pub fn moves(&self) -> &[PlayedMove] {
+        &self.moves
+    }
+
+    /// Number of moves shown on the board
+    pub fn current(&self) -> usize {
+        self.current
+    }
+}
+
+/// Go one move forward with the right arrow, and one back with the left one,
+/// or straight to the move picked in the move list
+#[allow(clippy::too_many_arguments)]
+fn step_replay(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    replay: Option<ResMut<Replay>>,
+    meshes: Res<PieceMeshes>,
+    mut turn: ResMut<PlayerTurn>,
+    mut last_double_push: ResMut<LastDoublePush>,
+    mut clocks: ResMut<MoveClocks>,
+    mut history: ResMut<MoveHistory>,
+    mut pieces_query: Query<(Entity, &mut Piece), Without<Taken>>,
+    mut jump_event: EventReader<JumpToMoveEvent>,
+) {
+    let mut replay = match replay {
+        Some(replay) => replay,
+        None => return,
+    };
+
+    let jump = jump_event.iter().last().map(|event| event.0);
+    if let Some(count) = jump.filter(|count| *count <= replay.moves.len()) {
+        replay.current = count;
+    } else if keyboard_input.just_pressed(KeyCode::Right) && replay.current < replay.moves.len() {
+        replay.current += 1;
+    } else if keyboard_input.just_pressed(KeyCode::Left) && replay.current > 0 {
+        replay.current -= 1;
+    } else {
+        return;
+    }
+
+    let position = &replay.positions[replay.current];
+    show_position(&mut commands, &meshes, &mut pieces_query, position);
+    set_game_state(position, &mut turn, &mut last_double_push, &mut clocks);
+    history.moves = replay.moves[..replay.current].to_vec();
+}
+
+/// A new game goes back to the start of the replay
+fn rewind_replay(mut event_reader: EventReader<NewGameEvent>, replay: Option<ResMut<Replay>>) {
+    if let (Some(_), Some(mut replay)) = (event_reader.iter().last(), replay) {
+        replay.current = 0;
+    }
+}
+
+pub struct ReplayPlugin;
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_update(AppState::Playing).with_system(step_replay))
+            .add_system(rewind_replay);
+    }
+}
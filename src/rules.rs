@@ -0,0 +1,1291 @@
+use crate::pieces::{Piece, PieceColor, PieceType};
+
+// Coordinates are the same ones used by `Piece`: x is the row (0 is White's back row)
+// and y is the column (0 is the a-file)
+
+/// FEN of the position at the start of a game
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+const KNIGHT_OFFSETS: [(i8, i8); 8] = [
+    (2, 1),
+    (2, -1),
+    (-2, 1),
+    (-2, -1),
+    (1, 2),
+    (1, -2),
+    (-1, 2),
+    (-1, -2),
+];
+const KING_OFFSETS: [(i8, i8); 8] = [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (1, -1),
+    (-1, 1),
+    (-1, -1),
+];
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+const PROMOTION_TYPES: [PieceType; 4] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+];
+
+impl PieceColor {
+    pub fn opponent(self) -> PieceColor {
+        match self {
+            PieceColor::White => PieceColor::Black,
+            PieceColor::Black => PieceColor::White,
+        }
+    }
+
+    /// Direction in which the pawns of this color move
+    fn forward(self) -> i8 {
+        match self {
+            PieceColor::White => 1,
+            PieceColor::Black => -1,
+        }
+    }
+
+    /// Row where the king and rooks of this color start
+    fn back_row(self) -> u8 {
+        match self {
+            PieceColor::White => 0,
+            PieceColor::Black => 7,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Move {
+    pub from: (u8, u8),
+    pub to: (u8, u8),
+    /// Piece a pawn turns into when it reaches the last row
+    pub promotion: Option<PieceType>,
+}
+impl Move {
+    /// Writes the move in the notation of the Universal Chess Interface, like "e2e4" or "e7e8q"
+    pub fn to_uci(&self) -> String {
+        let mut uci = square_name(self.from) + &square_name(self.to);
+        if let Some(promotion) = self.promotion {
+            uci.push(piece_to_char((PieceColor::Black, promotion)));
+        }
+        uci
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct CastlingRights {
+    pub white_kingside: bool,
+    pub white_queenside: bool,
+    pub black_kingside: bool,
+    pub black_queenside: bool,
+}
+impl CastlingRights {
+    fn get(&self, color: PieceColor, kingside: bool) -> bool {
+        match (color, kingside) {
+            (PieceColor::White, true) => self.white_kingside,
+            (PieceColor::White, false) => self.white_queenside,
+            (PieceColor::Black, true) => self.black_kingside,
+            (PieceColor::Black, false) => self.black_queenside,
+        }
+    }
+
+    /// Removes the rights that depend on a piece standing in `square`,
+    /// used whenever something moves from or to it
+    fn remove_for_square(&mut self, square: (u8, u8)) {
+        match square {
+            (0, 0) => self.white_queenside = false,
+            (0, 7) => self.white_kingside = false,
+            (0, 4) => {
+                self.white_kingside = false;
+                self.white_queenside = false;
+            }
+            (7, 0) => self.black_queenside = false,
+            (7, 7) => self.black_kingside = false,
+            (7, 4) => {
+                self.black_kingside = false;
+                self.black_queenside = false;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A chess position that doesn't depend on Bevy, used to generate and play legal moves
+#[derive(Clone, PartialEq, Debug)]
+pub struct Position {
+    /// Indexed as `squares[x][y]`
+    pub squares: [[Option<(PieceColor, PieceType)>; 8]; 8],
+    pub turn: PieceColor,
+    pub castling: CastlingRights,
+    /// Square a pawn can move to in order to take en passant
+    pub en_passant: Option<(u8, u8)>,
+    /// Half moves since the last capture or pawn move
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+}
+
+impl Position {
+    /// Builds the position from the pieces on the board.
+    /// `last_double_push` is the square of the pawn that moved two squares on the last move
+    pub fn from_pieces(
+        pieces: &[Piece],
+        turn: PieceColor,
+        last_double_push: Option<(u8, u8)>,
+    ) -> Position {
+        let mut squares = [[None; 8]; 8];
+        for piece in pieces {
+            squares[piece.x as usize][piece.y as usize] = Some((piece.color, piece.piece_type));
+        }
+
+        // Castling is possible while the king and the rook haven't moved
+        let unmoved = |piece_type: PieceType, square: (u8, u8)| {
+            pieces.iter().any(|piece| {
+                piece.piece_type == piece_type && (piece.x, piece.y) == square && !piece.has_moved
+            })
+        };
+        let castling = CastlingRights {
+            white_kingside: unmoved(PieceType::King, (0, 4)) && unmoved(PieceType::Rook, (0, 7)),
+            white_queenside: unmoved(PieceType::King, (0, 4)) && unmoved(PieceType::Rook, (0, 0)),
+            black_kingside: unmoved(PieceType::King, (7, 4)) && unmoved(PieceType::Rook, (7, 7)),
+            black_queenside: unmoved(PieceType::King, (7, 4)) && unmoved(PieceType::Rook, (7, 0)),
+        };
+
+        Position {
+            squares,
+            turn,
+            castling,
+            en_passant: last_double_push.map(|(x, y)| (if x == 3 { 2 } else { 5 }, y)),
+            halfmove_clock: 0,
+            fullmove_number: 1,
+        }
+    }
+
+    /// Parses a position in Forsyth-Edwards Notation.
+    /// The halfmove clock and fullmove number can be left out, they default to 0 and 1
+    pub fn from_fen(fen: &str) -> Result<Position, String> {
+        let mut fields = fen.split_whitespace();
+
+        let placement = fields.next().ok_or("FEN is empty")?;
+        let rows: Vec<&str> = placement.split('/').collect();
+        if rows.len() != 8 {
+            return Err(format!("expected 8 rows, found {}", rows.len()));
+        }
+
+        let mut squares = [[None; 8]; 8];
+        // FEN starts from Black's back row
+        for (row, x) in rows.iter().zip((0..8).rev()) {
+            let mut y = 0;
+            for c in row.chars() {
+                if let Some(empty) = c.to_digit(10) {
+                    y += empty as usize;
+                } else {
+                    let piece = piece_from_char(c)
+                        .ok_or_else(|| format!("unknown piece '{}' in FEN", c))?;
+                    if y >= 8 {
+                        return Err(format!("row '{}' has more than 8 squares", row));
+                    }
+                    squares[x][y] = Some(piece);
+                    y += 1;
+                }
+            }
+            if y != 8 {
+                return Err(format!("row '{}' doesn't have 8 squares", row));
+            }
+        }
+        for color in [PieceColor::White, PieceColor::Black] {
+            let kings = squares
+                .iter()
+                .flatten()
+                .filter(|&&piece| piece == Some((color, PieceType::King)))
+                .count();
+            if kings != 1 {
+                return Err(format!("{:?} has {} kings, expected 1", color, kings));
+            }
+        }
+
+        let turn = match fields.next() {
+            Some("w") => PieceColor::White,
+            Some("b") => PieceColor::Black,
+            other => return Err(format!("invalid side to move {:?}", other)),
+        };
+
+        let mut castling = CastlingRights::default();
+        match fields.next() {
+            Some("-") => {}
+            Some(rights) => {
+                for c in rights.chars() {
+                    match c {
+                        'K' => castling.white_kingside = true,
+                        'Q' => castling.white_queenside = true,
+                        'k' => castling.black_kingside = true,
+                        'q' => castling.black_queenside = true,
+                        _ => return Err(format!("invalid castling rights '{}'", rights)),
+                    }
+                }
+            }
+            None => return Err("missing castling rights".to_string()),
+        }
+
+        let en_passant = match fields.next() {
+            Some("-") => None,
+            Some(square) => Some(
+                parse_square(square)
+                    .ok_or_else(|| format!("invalid en passant square '{}'", square))?,
+            ),
+            None => return Err("missing en passant square".to_string()),
+        };
+        // The square must be just behind a pawn of the other side that moved two squares
+        if let Some((x, y)) = en_passant {
+            let pusher = turn.opponent();
+            let pawn_row = pusher.back_row() as i8 + 3 * pusher.forward();
+            let valid = x as i8 + pusher.forward() == pawn_row
+                && squares[x as usize][y as usize].is_none()
+                && squares[pawn_row as usize][y as usize] == Some((pusher, PieceType::Pawn));
+            if !valid {
+                return Err(format!(
+                    "invalid en passant square '{}'",
+                    square_name((x, y))
+                ));
+            }
+        }
+
+        let halfmove_clock = match fields.next() {
+            Some(clock) => clock
+                .parse()
+                .map_err(|_| format!("invalid halfmove clock '{}'", clock))?,
+            None => 0,
+        };
+        let fullmove_number = match fields.next() {
+            Some(number) => number
+                .parse()
+                .map_err(|_| format!("invalid fullmove number '{}'", number))?,
+            None => 1,
+        };
+
+        Ok(Position {
+            squares,
+            turn,
+            castling,
+            en_passant,
+            halfmove_clock,
+            fullmove_number,
+        })
+    }
+
+    /// Writes the position in Forsyth-Edwards Notation
+    pub fn to_fen(&self) -> String {
+        let mut placement = Vec::new();
+        // FEN starts from Black's back row
+        for x in (0..8).rev() {
+            let mut row = String::new();
+            let mut empty = 0;
+            for y in 0..8 {
+                match self.piece_at((x, y)) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            row.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        row.push(piece_to_char(piece));
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                row.push_str(&empty.to_string());
+            }
+            placement.push(row);
+        }
+
+        let turn = match self.turn {
+            PieceColor::White => "w",
+            PieceColor::Black => "b",
+        };
+
+        let mut castling = String::new();
+        for (right, c) in [
+            (self.castling.white_kingside, 'K'),
+            (self.castling.white_queenside, 'Q'),
+            (self.castling.black_kingside, 'k'),
+            (self.castling.black_queenside, 'q'),
+        ] {
+            if right {
+                castling.push(c);
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        let en_passant = match self.en_passant {
+            Some(square) => square_name(square),
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{} {} {} {} {} {}",
+            placement.join("/"),
+            turn,
+            castling,
+            en_passant,
+            self.halfmove_clock,
+            self.fullmove_number
+        )
+    }
+
+    /// Pieces of the position. Kings and rooks get `has_moved` set from the castling rights,
+    /// so `from_pieces` gives the same rights back
+    pub fn to_pieces(&self) -> Vec<Piece> {
+        all_squares()
+            .filter_map(|(x, y)| {
+                let (color, piece_type) = self.piece_at((x, y))?;
+                let has_moved = match (piece_type, color, (x, y)) {
+                    (PieceType::King, PieceColor::White, (0, 4)) => {
+                        !self.castling.white_kingside && !self.castling.white_queenside
+                    }
+                    (PieceType::King, PieceColor::Black, (7, 4)) => {
+                        !self.castling.black_kingside && !self.castling.black_queenside
+                    }
+                    (PieceType::Rook, PieceColor::White, (0, 7)) => !self.castling.white_kingside,
+                    (PieceType::Rook, PieceColor::White, (0, 0)) => !self.castling.white_queenside,
+                    (PieceType::Rook, PieceColor::Black, (7, 7)) => !self.castling.black_kingside,
+                    (PieceType::Rook, PieceColor::Black, (7, 0)) => !self.castling.black_queenside,
+                    (PieceType::King, _, _) | (PieceType::Rook, _, _) => true,
+                    _ => false,
+                };
+
+                Some(Piece {
+                    color,
+                    piece_type,
+                    x,
+                    y,
+                    has_moved,
+                })
+            })
+            .collect()
+    }
+
+    /// Square of the pawn that just moved two squares, the `last_double_push` of `from_pieces`
+    pub fn last_double_push(&self) -> Option<(u8, u8)> {
+        self.en_passant
+            .map(|(x, y)| (if x == 2 { 3 } else { 4 }, y))
+    }
+
+    pub fn piece_at(&self, square: (u8, u8)) -> Option<(PieceColor, PieceType)> {
+        self.squares[square.0 as usize][square.1 as usize]
+    }
+
+    /// Square of the king of `color`, if it is on the board
+    pub fn king_square(&self, color: PieceColor) -> Option<(u8, u8)> {
+        all_squares().find(|square| self.piece_at(*square) == Some((color, PieceType::King)))
+    }
+
+    /// Returns true if any piece of `color` attacks the square
+    pub fn is_square_attacked(&self, square: (u8, u8), color: PieceColor) -> bool {
+        // Pawns attack diagonally forward, so look for them diagonally backwards
+        for dy in [-1, 1] {
+            if let Some(from) = offset(square, -color.forward(), dy) {
+                if self.piece_at(from) == Some((color, PieceType::Pawn)) {
+                    return true;
+                }
+            }
+        }
+
+        for (dx, dy) in KNIGHT_OFFSETS {
+            if let Some(from) = offset(square, dx, dy) {
+                if self.piece_at(from) == Some((color, PieceType::Knight)) {
+                    return true;
+                }
+            }
+        }
+
+        for (dx, dy) in KING_OFFSETS {
+            if let Some(from) = offset(square, dx, dy) {
+                if self.piece_at(from) == Some((color, PieceType::King)) {
+                    return true;
+                }
+            }
+        }
+
+        let sliders = [
+            (ROOK_DIRECTIONS, PieceType::Rook),
+            (BISHOP_DIRECTIONS, PieceType::Bishop),
+        ];
+        for (directions, slider) in sliders {
+            for (dx, dy) in directions {
+                // Walk until the first piece in this direction
+                let mut current = square;
+                while let Some(next) = offset(current, dx, dy) {
+                    if let Some((piece_color, piece_type)) = self.piece_at(next) {
+                        if piece_color == color
+                            && (piece_type == slider || piece_type == PieceType::Queen)
+                        {
+                            return true;
+                        }
+                        break;
+                    }
+                    current = next;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Returns true if the king of `color` is attacked
+    pub fn is_in_check(&self, color: PieceColor) -> bool {
+        match self.king_square(color) {
+            Some(square) => self.is_square_attacked(square, color.opponent()),
+            None => false,
+        }
+    }
+
+    /// Every legal move for the side to move, including castling, en passant and promotions
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for square in all_squares() {
+            self.add_pseudo_legal_moves(square, &mut moves);
+        }
+        self.retain_legal(moves)
+    }
+
+    /// Legal moves of the piece in `from`, empty if it isn't one of the side to move
+    pub fn legal_moves_from(&self, from: (u8, u8)) -> Vec<Move> {
+        let mut moves = Vec::new();
+        self.add_pseudo_legal_moves(from, &mut moves);
+        self.retain_legal(moves)
+    }
+
+    /// Plays a move, which is expected to be legal in this position
+    pub fn make_move(&mut self, mv: Move) {
+        let (color, piece_type) = match self.piece_at(mv.from) {
+            Some(piece) => piece,
+            None => return,
+        };
+        let captured = self.piece_at(mv.to);
+
+        // Taking en passant removes the pawn beside the one that moves
+        if piece_type == PieceType::Pawn && Some(mv.to) == self.en_passant && mv.from.1 != mv.to.1 {
+            self.squares[mv.from.0 as usize][mv.to.1 as usize] = None;
+        }
+
+        // Castling moves the rook to the other side of the king
+        if piece_type == PieceType::King && (mv.from.1 as i8 - mv.to.1 as i8).abs() == 2 {
+            let (rook_y, new_rook_y) = if mv.to.1 > mv.from.1 { (7, 5) } else { (0, 3) };
+            let row = mv.from.0 as usize;
+            self.squares[row][new_rook_y] = self.squares[row][rook_y].take();
+        }
+
+        self.squares[mv.from.0 as usize][mv.from.1 as usize] = None;
+        self.squares[mv.to.0 as usize][mv.to.1 as usize] =
+            Some((color, mv.promotion.unwrap_or(piece_type)));
+
+        self.castling.remove_for_square(mv.from);
+        self.castling.remove_for_square(mv.to);
+
+        self.en_passant =
+            if piece_type == PieceType::Pawn && (mv.from.0 as i8 - mv.to.0 as i8).abs() == 2 {
+                Some(((mv.from.0 + mv.to.0) / 2, mv.from.1))
+            } else {
+                None
+            };
+
+        if piece_type == PieceType::Pawn || captured.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock = self.halfmove_clock.saturating_add(1);
+        }
+        if color == PieceColor::Black {
+            self.fullmove_number = self.fullmove_number.saturating_add(1);
+        }
+
+        self.turn = color.opponent();
+    }
+
+    /// Writes a legal move in Standard Algebraic Notation, like "Nbd7", "exd6", "e8=Q+" or "O-O"
+    pub fn san(&self, mv: Move) -> String {
+        let piece_type = match self.piece_at(mv.from) {
+            Some((_, piece_type)) => piece_type,
+            None => return String::new(),
+        };
+
+        let mut san = String::new();
+        if piece_type == PieceType::King && (mv.from.1 as i8 - mv.to.1 as i8).abs() == 2 {
+            san.push_str(if mv.to.1 > mv.from.1 { "O-O" } else { "O-O-O" });
+        } else {
+            let captures = self.piece_at(mv.to).is_some()
+                || (piece_type == PieceType::Pawn && mv.from.1 != mv.to.1);
+
+            if piece_type == PieceType::Pawn {
+                // Pawn captures start with the column the pawn comes from
+                if captures {
+                    san.push((b'a' + mv.from.1) as char);
+                }
+            } else {
+                san.push(piece_letter(piece_type));
+
+                // Add the column, the row or both if another piece of the same type
+                // could also move to the same square
+                let others: Vec<(u8, u8)> = self
+                    .legal_moves()
+                    .into_iter()
+                    .filter(|other| {
+                        other.to == mv.to
+                            && other.from != mv.from
+                            && self.piece_at(other.from) == self.piece_at(mv.from)
+                    })
+                    .map(|other| other.from)
+                    .collect();
+                if !others.is_empty() {
+                    if others.iter().all(|other| other.1 != mv.from.1) {
+                        san.push((b'a' + mv.from.1) as char);
+                    } else if others.iter().all(|other| other.0 != mv.from.0) {
+                        san.push((b'1' + mv.from.0) as char);
+                    } else {
+                        san.push_str(&square_name(mv.from));
+                    }
+                }
+            }
+
+            if captures {
+                san.push('x');
+            }
+            san.push_str(&square_name(mv.to));
+
+            if let Some(promotion) = mv.promotion {
+                san.push('=');
+                san.push(piece_letter(promotion));
+            }
+        }
+
+        let mut position = self.clone();
+        position.make_move(mv);
+        if position.is_in_check(position.turn) {
+            san.push(if position.legal_moves().is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+
+        san
+    }
+
+    /// Finds the legal move written in Standard Algebraic Notation.
+    /// Check marks and annotations like "!?" are ignored
+    pub fn parse_san(&self, san: &str) -> Result<Move, String> {
+        let notation = san.trim().trim_end_matches(|c| "+#!?".contains(c));
+
+        let king_to = match notation {
+            "O-O" | "0-0" => Some(6),
+            "O-O-O" | "0-0-0" => Some(2),
+            _ => None,
+        };
+        if let Some(king_to) = king_to {
+            let row = self.turn.back_row();
+            return self
+                .legal_moves_from((row, 4))
+                .into_iter()
+                .find(|mv| {
+                    mv.to == (row, king_to)
+                        && self.piece_at(mv.from).map(|piece| piece.1) == Some(PieceType::King)
+                })
+                .ok_or_else(|| format!("{} is not a legal move", san));
+        }
+
+        let mut chars: Vec<char> = notation.chars().collect();
+
+        let piece_type = match chars.first().copied().and_then(piece_from_char) {
+            Some((PieceColor::White, piece_type)) if piece_type != PieceType::Pawn => {
+                chars.remove(0);
+                piece_type
+            }
+            _ => PieceType::Pawn,
+        };
+
+        // Promotions are written "e8=Q", but "e8Q" is accepted too
+        let mut promotion = None;
+        if piece_type == PieceType::Pawn {
+            if let Some(Some((PieceColor::White, promoted))) =
+                chars.last().map(|c| piece_from_char(*c))
+            {
+                promotion = Some(promoted);
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+            }
+        }
+
+        if chars.len() < 2 {
+            return Err(format!("{} is not a valid move", san));
+        }
+        let to: String = chars.split_off(chars.len() - 2).into_iter().collect();
+        let to = parse_square(&to).ok_or_else(|| format!("{} is not a valid move", san))?;
+
+        // Whatever is left is the capture mark and the column or row the piece comes from
+        let mut from_column = None;
+        let mut from_row = None;
+        for c in chars {
+            match c {
+                'x' => {}
+                'a'..='h' => from_column = Some(c as u8 - b'a'),
+                '1'..='8' => from_row = Some(c as u8 - b'1'),
+                _ => return Err(format!("{} is not a valid move", san)),
+            }
+        }
+
+        let candidates: Vec<Move> = self
+            .legal_moves()
+            .into_iter()
+            .filter(|mv| {
+                mv.to == to
+                    && mv.promotion == promotion
+                    && self.piece_at(mv.from).map(|piece| piece.1) == Some(piece_type)
+                    && from_column.map_or(true, |y| mv.from.1 == y)
+                    && from_row.map_or(true, |x| mv.from.0 == x)
+            })
+            .collect();
+
+        match candidates.len() {
+            0 => Err(format!("{} is not a legal move", san)),
+            1 => Ok(candidates[0]),
+            _ => Err(format!("{} is ambiguous", san)),
+        }
+    }
+
+    /// Finds the legal move written in the notation of the Universal Chess Interface
+    pub fn parse_uci(&self, uci: &str) -> Result<Move, String> {
+        let invalid = || format!("{} is not a valid move", uci);
+        let from = uci.get(0..2).and_then(parse_square).ok_or_else(invalid)?;
+        let to = uci.get(2..4).and_then(parse_square).ok_or_else(invalid)?;
+        let promotion = match uci.get(4..) {
+            Some("") => None,
+            Some(letter) => match letter.chars().next().and_then(piece_from_char) {
+                Some((PieceColor::Black, piece_type)) if letter.len() == 1 => Some(piece_type),
+                _ => return Err(invalid()),
+            },
+            None => return Err(invalid()),
+        };
+
+        let mv = Move {
+            from,
+            to,
+            promotion,
+        };
+        if self.legal_moves_from(from).contains(&mv) {
+            Ok(mv)
+        } else {
+            Err(format!("{} is not a legal move", uci))
+        }
+    }
+
+    /// Counts the leaf nodes of the move tree up to `depth`, used to check the move generator
+    pub fn perft(&self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        let moves = self.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+
+        moves
+            .into_iter()
+            .map(|mv| {
+                let mut position = self.clone();
+                position.make_move(mv);
+                position.perft(depth - 1)
+            })
+            .sum()
+    }
+
+    /// Keeps the moves that don't leave the king of the side to move in check
+    fn retain_legal(&self, moves: Vec<Move>) -> Vec<Move> {
+        moves
+            .into_iter()
+            .filter(|mv| {
+                let mut position = self.clone();
+                position.make_move(*mv);
+                !position.is_in_check(self.turn)
+            })
+            .collect()
+    }
+
+    /// Moves of the piece in `from` that follow its movement rules,
+    /// without looking at whether they leave the king in check
+    fn add_pseudo_legal_moves(&self, from: (u8, u8), moves: &mut Vec<Move>) {
+        let (color, piece_type) = match self.piece_at(from) {
+            Some(piece) if piece.0 == self.turn => piece,
+            _ => return,
+        };
+
+        match piece_type {
+            PieceType::Pawn => self.add_pawn_moves(from, color, moves),
+            PieceType::Knight => self.add_step_moves(from, color, &KNIGHT_OFFSETS, moves),
+            PieceType::King => {
+                self.add_step_moves(from, color, &KING_OFFSETS, moves);
+                self.add_castling_moves(from, color, moves);
+            }
+            PieceType::Rook => self.add_sliding_moves(from, color, &ROOK_DIRECTIONS, moves),
+            PieceType::Bishop => self.add_sliding_moves(from, color, &BISHOP_DIRECTIONS, moves),
+            PieceType::Queen => {
+                self.add_sliding_moves(from, color, &ROOK_DIRECTIONS, moves);
+                self.add_sliding_moves(from, color, &BISHOP_DIRECTIONS, moves);
+            }
+        }
+    }
+
+    fn add_pawn_moves(&self, from: (u8, u8), color: PieceColor, moves: &mut Vec<Move>) {
+        let forward = color.forward();
+        let start_row = match color {
+            PieceColor::White => 1,
+            PieceColor::Black => 6,
+        };
+
+        // Pawns reaching the last row add one move per promotion choice
+        let mut add = |to: (u8, u8)| {
+            if to.0 == 0 || to.0 == 7 {
+                for promotion in PROMOTION_TYPES {
+                    moves.push(Move {
+                        from,
+                        to,
+                        promotion: Some(promotion),
+                    });
+                }
+            } else {
+                moves.push(Move {
+                    from,
+                    to,
+                    promotion: None,
+                });
+            }
+        };
+
+        // Normal move, and 2 squares from the starting row
+        if let Some(to) = offset(from, forward, 0) {
+            if self.piece_at(to).is_none() {
+                add(to);
+
+                if from.0 == start_row {
+                    if let Some(to) = offset(from, 2 * forward, 0) {
+                        if self.piece_at(to).is_none() {
+                            add(to);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Take piece, normally or en passant
+        for dy in [-1, 1] {
+            if let Some(to) = offset(from, forward, dy) {
+                let takes = match self.piece_at(to) {
+                    Some((other_color, _)) => other_color != color,
+                    None => self.en_passant == Some(to),
+                };
+                if takes {
+                    add(to);
+                }
+            }
+        }
+    }
+
+    fn add_step_moves(
+        &self,
+        from: (u8, u8),
+        color: PieceColor,
+        offsets: &[(i8, i8)],
+        moves: &mut Vec<Move>,
+    ) {
+        for (dx, dy) in offsets {
+            if let Some(to) = offset(from, *dx, *dy) {
+                if self.piece_at(to).map(|(other_color, _)| other_color) != Some(color) {
+                    moves.push(Move {
+                        from,
+                        to,
+                        promotion: None,
+                    });
+                }
+            }
+        }
+    }
+
+    fn add_sliding_moves(
+        &self,
+        from: (u8, u8),
+        color: PieceColor,
+        directions: &[(i8, i8)],
+        moves: &mut Vec<Move>,
+    ) {
+        for (dx, dy) in directions {
+            let mut current = from;
+            while let Some(to) = offset(current, *dx, *dy) {
+                match self.piece_at(to) {
+                    None => moves.push(Move {
+                        from,
+                        to,
+                        promotion: None,
+                    }),
+                    Some((other_color, _)) => {
+                        if other_color != color {
+                            moves.push(Move {
+                                from,
+                                to,
+                                promotion: None,
+                            });
+                        }
+                        break;
+                    }
+                }
+                current = to;
+            }
+        }
+    }
+
+    /// The king moves two squares towards a rook that hasn't moved. The squares between them
+    /// must be empty, and the king can't be in check, pass through an attacked square or end up in check
+    fn add_castling_moves(&self, from: (u8, u8), color: PieceColor, moves: &mut Vec<Move>) {
+        let row = color.back_row();
+        if from != (row, 4) {
+            return;
+        }
+
+        for kingside in [true, false] {
+            if !self.castling.get(color, kingside) {
+                continue;
+            }
+
+            let (rook_y, between, king_path): (u8, &[u8], [u8; 3]) = if kingside {
+                (7, &[5, 6], [4, 5, 6])
+            } else {
+                (0, &[1, 2, 3], [4, 3, 2])
+            };
+
+            if self.piece_at((row, rook_y)) != Some((color, PieceType::Rook))
+                || between.iter().any(|y| self.piece_at((row, *y)).is_some())
+                || king_path
+                    .iter()
+                    .any(|y| self.is_square_attacked((row, *y), color.opponent()))
+            {
+                continue;
+            }
+
+            moves.push(Move {
+                from,
+                to: (row, king_path[2]),
+                promotion: None,
+            });
+        }
+    }
+}
+
+/// Parses a square in algebraic notation, like "e4"
+pub fn parse_square(name: &str) -> Option<(u8, u8)> {
+    let mut chars = name.chars();
+    let file = chars.next()?;
+    let rank = chars.next()?;
+    if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    Some((rank as u8 - b'1', file as u8 - b'a'))
+}
+
+/// Name of a square in algebraic notation, like "e4"
+pub fn square_name(square: (u8, u8)) -> String {
+    format!("{}{}", (b'a' + square.1) as char, square.0 + 1)
+}
+
+/// Piece for a FEN letter, uppercase is White and lowercase is Black
+fn piece_from_char(c: char) -> Option<(PieceColor, PieceType)> {
+    let color = if c.is_ascii_uppercase() {
+        PieceColor::White
+    } else {
+        PieceColor::Black
+    };
+    let piece_type = match c.to_ascii_lowercase() {
+        'k' => PieceType::King,
+        'q' => PieceType::Queen,
+        'b' => PieceType::Bishop,
+        'n' => PieceType::Knight,
+        'r' => PieceType::Rook,
+        'p' => PieceType::Pawn,
+        _ => return None,
+    };
+    Some((color, piece_type))
+}
+
+/// FEN letter of a piece, uppercase for White and lowercase for Black
+fn piece_to_char((color, piece_type): (PieceColor, PieceType)) -> char {
+    let c = match piece_type {
+        PieceType::King => 'k',
+        PieceType::Queen => 'q',
+        PieceType::Bishop => 'b',
+        PieceType::Knight => 'n',
+        PieceType::Rook => 'r',
+        PieceType::Pawn => 'p',
+    };
+    match color {
+        PieceColor::White => c.to_ascii_uppercase(),
+        PieceColor::Black => c,
+    }
+}
+
+/// Letter used for a piece type in algebraic notation
+fn piece_letter(piece_type: PieceType) -> char {
+    piece_to_char((PieceColor::White, piece_type))
+}
+
+/// Every square of the board
+fn all_squares() -> impl Iterator<Item = (u8, u8)> {
+    (0..8).flat_map(|x| (0..8).map(move |y| (x, y)))
+}
+
+/// Moves a square by (dx, dy), returning None if it falls off the board
+fn offset(square: (u8, u8), dx: i8, dy: i8) -> Option<(u8, u8)> {
+    let x = square.0 as i8 + dx;
+    let y = square.1 as i8 + dy;
+    if (0..8).contains(&x) && (0..8).contains(&y) {
+        Some((x as u8, y as u8))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reference positions and node counts from https://www.chessprogramming.org/Perft_Results
+
+    fn assert_perft(fen: &str, expected: &[u64]) {
+        let position = Position::from_fen(fen).unwrap();
+        for (depth, nodes) in expected.iter().enumerate() {
+            assert_eq!(
+                position.perft(depth as u32 + 1),
+                *nodes,
+                "perft({}) of {}",
+                depth + 1,
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn perft_starting_position() {
+        assert_perft(STARTING_FEN, &[20, 400, 8902, 197281]);
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        assert_perft(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2039, 97862],
+        );
+    }
+
+    #[test]
+    fn perft_position_3() {
+        assert_perft(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2812, 43238],
+        );
+    }
+
+    #[test]
+    fn perft_position_4() {
+        assert_perft(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9467],
+        );
+    }
+
+    #[test]
+    fn perft_position_4_mirrored() {
+        assert_perft(
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+            &[6, 264, 9467],
+        );
+    }
+
+    #[test]
+    fn perft_position_5() {
+        assert_perft(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1486, 62379],
+        );
+    }
+
+    #[test]
+    fn perft_position_6() {
+        assert_perft(
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            &[46, 2079, 89890],
+        );
+    }
+
+    #[test]
+    fn from_pieces_matches_fen() {
+        let position = Position::from_fen(STARTING_FEN).unwrap();
+        let mut pieces = Vec::new();
+        for x in 0..8 {
+            for y in 0..8 {
+                if let Some((color, piece_type)) = position.piece_at((x, y)) {
+                    pieces.push(Piece {
+                        color,
+                        piece_type,
+                        x,
+                        y,
+                        has_moved: false,
+                    });
+                }
+            }
+        }
+
+        assert_eq!(
+            Position::from_pieces(&pieces, PieceColor::White, None),
+            position
+        );
+    }
+
+    #[test]
+    fn pieces_round_trip() {
+        for fen in [
+            STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R b Qk - 0 1",
+            "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3",
+        ] {
+            let position = Position::from_fen(fen).unwrap();
+            assert_eq!(
+                Position::from_pieces(
+                    &position.to_pieces(),
+                    position.turn,
+                    position.last_double_push()
+                ),
+                Position {
+                    halfmove_clock: 0,
+                    fullmove_number: 1,
+                    ..position.clone()
+                },
+                "{}",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in [
+            STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3",
+        ] {
+            assert_eq!(Position::from_fen(fen).unwrap().to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn san_of_special_moves() {
+        let san = |fen: &str, from: &str, to: &str, promotion: Option<PieceType>| {
+            Position::from_fen(fen).unwrap().san(Move {
+                from: parse_square(from).unwrap(),
+                to: parse_square(to).unwrap(),
+                promotion,
+            })
+        };
+
+        assert_eq!(san(STARTING_FEN, "g1", "f3", None), "Nf3");
+        assert_eq!(san(STARTING_FEN, "e2", "e4", None), "e4");
+        assert_eq!(
+            san("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1", "g1", None),
+            "O-O"
+        );
+        assert_eq!(
+            san("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8", "c8", None),
+            "O-O-O"
+        );
+        // Both rooks can reach d1
+        assert_eq!(
+            san("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", "a1", "d1", None),
+            "Rad1"
+        );
+        // Both rooks can reach a4
+        assert_eq!(
+            san("4k3/8/R7/8/8/8/R7/4K3 w - - 0 1", "a2", "a4", None),
+            "R2a4"
+        );
+        // Queens on the same row, and then also on the same column
+        assert_eq!(
+            san("2k5/8/8/8/Q6Q/8/8/4K3 w - - 0 1", "h4", "e4", None),
+            "Qhe4"
+        );
+        assert_eq!(
+            san("2k5/8/8/8/Q6Q/8/8/4K2Q w - - 0 1", "h4", "e4", None),
+            "Qh4e4"
+        );
+        assert_eq!(
+            san(
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+                "e5",
+                "f6",
+                None
+            ),
+            "exf6"
+        );
+        assert_eq!(
+            san(
+                "3k4/1P6/8/8/8/8/8/4K3 w - - 0 1",
+                "b7",
+                "b8",
+                Some(PieceType::Queen)
+            ),
+            "b8=Q+"
+        );
+        assert_eq!(
+            san("7k/8/6K1/8/8/8/8/R7 w - - 0 1", "a1", "a8", None),
+            "Ra8#"
+        );
+    }
+
+    #[test]
+    fn parse_san_finds_the_move() {
+        let parse = |fen: &str, san: &str| {
+            Position::from_fen(fen)
+                .unwrap()
+                .parse_san(san)
+                .map(|mv| (square_name(mv.from), square_name(mv.to), mv.promotion))
+        };
+        let two_rooks = "4k3/8/8/8/8/8/4K3/R6R w - - 0 1";
+
+        assert_eq!(
+            parse(STARTING_FEN, "Nf3"),
+            Ok(("g1".to_string(), "f3".to_string(), None))
+        );
+        assert_eq!(
+            parse(STARTING_FEN, "e4"),
+            Ok(("e2".to_string(), "e4".to_string(), None))
+        );
+        assert_eq!(
+            parse("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "O-O-O"),
+            Ok(("e8".to_string(), "c8".to_string(), None))
+        );
+        assert_eq!(
+            parse(two_rooks, "Rhd1+"),
+            Ok(("h1".to_string(), "d1".to_string(), None))
+        );
+        assert_eq!(
+            parse("3k4/1P6/8/8/8/8/8/4K3 w - - 0 1", "b8=N"),
+            Ok(("b7".to_string(), "b8".to_string(), Some(PieceType::Knight)))
+        );
+        assert_eq!(
+            parse(
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+                "exf6"
+            ),
+            Ok(("e5".to_string(), "f6".to_string(), None))
+        );
+
+        assert_eq!(parse(two_rooks, "Rd1"), Err("Rd1 is ambiguous".to_string()));
+        assert_eq!(
+            parse(STARTING_FEN, "e5"),
+            Err("e5 is not a legal move".to_string())
+        );
+        assert_eq!(
+            parse(STARTING_FEN, "O-O"),
+            Err("O-O is not a legal move".to_string())
+        );
+        assert_eq!(
+            parse(STARTING_FEN, "hello"),
+            Err("hello is not a valid move".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_san_reads_what_san_writes() {
+        let position = Position::from_fen(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        )
+        .unwrap();
+        for mv in position.legal_moves() {
+            assert_eq!(position.parse_san(&position.san(mv)), Ok(mv));
+        }
+    }
+
+    #[test]
+    fn parse_uci_reads_what_to_uci_writes() {
+        let position = Position::from_fen("r3k3/1P6/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        for mv in position.legal_moves() {
+            assert_eq!(position.parse_uci(&mv.to_uci()), Ok(mv));
+        }
+
+        assert_eq!(
+            position.parse_uci("b7a8n").map(|mv| mv.promotion),
+            Ok(Some(PieceType::Knight))
+        );
+        assert_eq!(
+            position.parse_uci("b7b8"),
+            Err("b7b8 is not a legal move".to_string())
+        );
+        assert_eq!(
+            position.parse_uci("e1g1x"),
+            Err("e1g1x is not a valid move".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_fen_is_rejected() {
+        assert!(Position::from_fen("").is_err());
+        assert!(Position::from_fen("8/8/8/8/8/8/8 w - - 0 1").is_err());
+        assert!(
+            Position::from_fen("rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -").is_err()
+        );
+        assert!(
+            Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq -").is_err()
+        );
+        assert!(
+            Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq -").is_err()
+        );
+        assert!(
+            Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e9").is_err()
+        );
+        // En passant squares that no pawn could have just crossed
+        assert!(
+            Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e3").is_err()
+        );
+        assert!(
+            Position::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e3").is_err()
+        );
+        assert!(
+            Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq e6").is_err()
+        );
+        assert!(
+            Position::from_fen("rnbqkbnr/pppp1ppp/8/4p3/8/8/PPPPPPPP/RNBQKBNR w KQkq d6").is_err()
+        );
+        // Each side needs exactly one king
+        assert!(Position::from_fen("8/8/8/8/8/8/8/4K3 w - -").is_err());
+        assert!(Position::from_fen("4k3/8/8/8/8/8/8/3KK3 w - -").is_err());
+    }
+
+    #[test]
+    fn move_counters_saturate() {
+        let mut position =
+            Position::from_fen("4k3/8/8/8/8/8/8/4K3 b - - 4294967295 4294967295").unwrap();
+        position.make_move(Move {
+            from: (7, 4),
+            to: (7, 3),
+            promotion: None,
+        });
+        assert_eq!(position.halfmove_clock, u32::MAX);
+        assert_eq!(position.fullmove_number, u32::MAX);
+    }
+}
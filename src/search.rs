@@ -0,0 +1,410 @@
+use crate::{
+    pieces::{PieceColor, PieceType},
+    rules::{Move, Position},
+};
+use std::{
+    cmp::Reverse,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+/// Score of a checkmate, the plies needed to reach it are taken off so faster mates score higher
+pub const MATE: i32 = 100_000;
+const INFINITY: i32 = 1_000_000;
+
+// Piece-square tables from White's side, with the eighth rank first so they read like a board
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+#[rustfmt::skip]
+const KING_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+/// Material value of a piece in centipawns
+pub fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 0,
+    }
+}
+
+/// Material and piece placement in centipawns, from the side to move's point of view
+pub fn evaluate(position: &Position) -> i32 {
+    let mut score = 0;
+    for x in 0..8 {
+        for y in 0..8 {
+            let (color, piece_type) = match position.squares[x][y] {
+                Some(piece) => piece,
+                None => continue,
+            };
+
+            let table = match piece_type {
+                PieceType::Pawn => &PAWN_TABLE,
+                PieceType::Knight => &KNIGHT_TABLE,
+                PieceType::Bishop => &BISHOP_TABLE,
+                PieceType::Rook => &ROOK_TABLE,
+                PieceType::Queen => &QUEEN_TABLE,
+                PieceType::King => &KING_TABLE,
+            };
+            // Black reads the tables upside down
+            let row = match color {
+                PieceColor::White => 7 - x,
+                PieceColor::Black => x,
+            };
+            let value = piece_value(piece_type) + table[row * 8 + y];
+
+            if color == position.turn {
+                score += value;
+            } else {
+                score -= value;
+            }
+        }
+    }
+    score
+}
+
+/// Outcome of a search, from the side to move's point of view
+#[derive(Clone, Copy, Debug)]
+pub struct SearchResult {
+    /// None when there are no legal moves
+    pub best_move: Option<Move>,
+    pub score: i32,
+    /// Deepest iteration that was searched to the end
+    pub depth: u32,
+    pub nodes: u64,
+}
+
+/// Looks for the best move with an alpha-beta search, deepening one ply at a time
+/// until `max_depth` is reached, `deadline` passes or `stop` is set.
+/// An iteration that is cut short is thrown away, the previous one gives the move
+pub fn search(
+    position: &Position,
+    max_depth: u32,
+    deadline: Option<Instant>,
+    stop: &AtomicBool,
+) -> SearchResult {
+    search_with_info(position, max_depth, deadline, stop, |_| {})
+}
+
+/// Same as `search`, calling `on_iteration` with the result of every finished iteration
+pub fn search_with_info(
+    position: &Position,
+    max_depth: u32,
+    deadline: Option<Instant>,
+    stop: &AtomicBool,
+    mut on_iteration: impl FnMut(&SearchResult),
+) -> SearchResult {
+    let mut searcher = Searcher {
+        nodes: 0,
+        deadline,
+        stop,
+        aborted: false,
+    };
+    let moves = position.legal_moves();
+    let mut result = SearchResult {
+        best_move: order_moves(position, moves.clone(), None).first().copied(),
+        score: 0,
+        depth: 0,
+        nodes: 0,
+    };
+
+    for depth in 1..=max_depth.max(1) {
+        let mut alpha = -INFINITY;
+        let mut best_move = None;
+        for mv in order_moves(position, moves.clone(), result.best_move) {
+            let mut next = position.clone();
+            next.make_move(mv);
+            let score = -searcher.negamax(&next, depth - 1, 1, -INFINITY, -alpha);
+            if searcher.aborted {
+                break;
+            }
+            if score > alpha {
+                alpha = score;
+                best_move = Some(mv);
+            }
+        }
+        if searcher.aborted || best_move.is_none() {
+            break;
+        }
+
+        result.best_move = best_move;
+        result.score = alpha;
+        result.depth = depth;
+        result.nodes = searcher.nodes;
+        on_iteration(&result);
+
+        // Searching deeper won't find a faster mate
+        if alpha.abs() >= MATE - depth as i32 {
+            break;
+        }
+    }
+
+    result.nodes = searcher.nodes;
+    result
+}
+
+struct Searcher<'a> {
+    nodes: u64,
+    deadline: Option<Instant>,
+    stop: &'a AtomicBool,
+    aborted: bool,
+}
+impl Searcher<'_> {
+    /// Checks the stop flag and the clock every few nodes
+    fn should_stop(&mut self) -> bool {
+        if !self.aborted && self.nodes % 1024 == 0 {
+            self.aborted = self.stop.load(Ordering::Relaxed)
+                || self
+                    .deadline
+                    .map_or(false, |deadline| Instant::now() >= deadline);
+        }
+        self.aborted
+    }
+
+    fn negamax(
+        &mut self,
+        position: &Position,
+        depth: u32,
+        ply: i32,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        if self.should_stop() {
+            return 0;
+        }
+        self.nodes += 1;
+
+        let moves = position.legal_moves();
+        if moves.is_empty() {
+            return if position.is_in_check(position.turn) {
+                -MATE + ply
+            } else {
+                0
+            };
+        }
+        // Fifty moves without a capture or a pawn move is a draw
+        if position.halfmove_clock >= 100 {
+            return 0;
+        }
+        if depth == 0 {
+            return self.quiescence(position, alpha, beta);
+        }
+
+        for mv in order_moves(position, moves, None) {
+            let mut next = position.clone();
+            next.make_move(mv);
+            let score = -self.negamax(&next, depth - 1, ply + 1, -beta, -alpha);
+            if self.aborted {
+                return 0;
+            }
+            if score >= beta {
+                return beta;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+
+    /// Keeps playing captures and promotions until the position is quiet,
+    /// so a piece isn't counted as won when it can be taken back
+    fn quiescence(&mut self, position: &Position, mut alpha: i32, beta: i32) -> i32 {
+        if self.should_stop() {
+            return 0;
+        }
+        self.nodes += 1;
+
+        let stand_pat = evaluate(position);
+        if stand_pat >= beta {
+            return beta;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let moves = position
+            .legal_moves()
+            .into_iter()
+            .filter(|mv| is_capture(position, *mv) || mv.promotion.is_some())
+            .collect();
+        for mv in order_moves(position, moves, None) {
+            let mut next = position.clone();
+            next.make_move(mv);
+            let score = -self.quiescence(&next, -beta, -alpha);
+            if self.aborted {
+                return 0;
+            }
+            if score >= beta {
+                return beta;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+}
+
+fn is_capture(position: &Position, mv: Move) -> bool {
+    position.piece_at(mv.to).is_some()
+        || (Some(mv.to) == position.en_passant
+            && position.piece_at(mv.from).map(|piece| piece.1) == Some(PieceType::Pawn))
+}
+
+/// Puts `first` at the front, then captures of valuable pieces by cheap ones and promotions
+fn order_moves(position: &Position, mut moves: Vec<Move>, first: Option<Move>) -> Vec<Move> {
+    moves.sort_by_key(|mv| {
+        if Some(*mv) == first {
+            return Reverse(INFINITY);
+        }
+
+        let mut score = mv.promotion.map_or(0, piece_value);
+        if is_capture(position, *mv) {
+            let victim = position
+                .piece_at(mv.to)
+                .map_or(PieceType::Pawn, |piece| piece.1);
+            let attacker = position
+                .piece_at(mv.from)
+                .map_or(PieceType::Pawn, |piece| piece.1);
+            score += 10 * piece_value(victim) - piece_value(attacker) + 1000;
+        }
+        Reverse(score)
+    });
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{parse_square, STARTING_FEN};
+
+    fn best_move(fen: &str, depth: u32) -> SearchResult {
+        let position = Position::from_fen(fen).unwrap();
+        search(&position, depth, None, &AtomicBool::new(false))
+    }
+
+    fn square_move(from: &str, to: &str) -> Option<Move> {
+        Some(Move {
+            from: parse_square(from).unwrap(),
+            to: parse_square(to).unwrap(),
+            promotion: None,
+        })
+    }
+
+    #[test]
+    fn starting_position_is_even() {
+        assert_eq!(evaluate(&Position::from_fen(STARTING_FEN).unwrap()), 0);
+    }
+
+    #[test]
+    fn evaluation_is_from_the_side_to_move() {
+        let white = Position::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+        let black = Position::from_fen("4k3/8/8/8/8/8/8/3QK3 b - - 0 1").unwrap();
+        assert!(evaluate(&white) > 800);
+        assert_eq!(evaluate(&white), -evaluate(&black));
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        let result = best_move("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3);
+        assert_eq!(result.best_move, square_move("a1", "a8"));
+        assert_eq!(result.score, MATE - 1);
+    }
+
+    #[test]
+    fn takes_a_hanging_queen() {
+        let result = best_move("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1", 2);
+        assert_eq!(result.best_move, square_move("d2", "d5"));
+    }
+
+    #[test]
+    fn leaves_a_defended_pawn_alone() {
+        // Qxd5 wins a pawn and loses the queen to exd5
+        let result = best_move("4k3/8/4p3/3p4/8/8/8/3QK3 w - - 0 1", 1);
+        assert_ne!(result.best_move, square_move("d1", "d5"));
+    }
+
+    #[test]
+    fn no_move_when_mated() {
+        let result = best_move("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1", 3);
+        assert_eq!(result.best_move, None);
+    }
+
+    #[test]
+    fn reports_every_iteration() {
+        let position = Position::from_fen(STARTING_FEN).unwrap();
+        let mut depths = Vec::new();
+        let result = search_with_info(&position, 3, None, &AtomicBool::new(false), |result| {
+            depths.push(result.depth)
+        });
+        assert_eq!(depths, vec![1, 2, 3]);
+        assert_eq!(result.depth, 3);
+    }
+
+    #[test]
+    fn stops_when_asked() {
+        let position = Position::from_fen(STARTING_FEN).unwrap();
+        let result = search(&position, 20, None, &AtomicBool::new(true));
+        assert_eq!(result.depth, 0);
+        assert!(result.best_move.is_some());
+    }
+}
@@ -0,0 +1,358 @@
+use crate::{
+    ai::AiPlayer,
+    board::*,
+    menu::playing,
+    pieces::*,
+    replay::Replay,
+    rules::{Move, Position},
+};
+use bevy::prelude::*;
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, TryRecvError},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long the engine has to answer `uci` and `isready` before we give up on it
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An engine process spoken to with the Universal Chess Interface
+pub struct UciEngine {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Mutex<Receiver<String>>,
+    /// Searches that were stopped, their `bestmove` isn't wanted anymore
+    stale_searches: usize,
+}
+impl UciEngine {
+    /// Starts the engine at `path` and waits until it's ready
+    pub fn start(path: &str) -> Result<Self, String> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|error| format!("couldn't start {}: {}", path, error))?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        // Lines are read on their own thread, so the game never blocks waiting for the engine
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let sent = line.map(|line| sender.send(line).is_ok());
+                if !matches!(sent, Ok(true)) {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = Self {
+            name: path.to_string(),
+            child,
+            stdin,
+            lines: Mutex::new(receiver),
+            stale_searches: 0,
+        };
+
+        engine.send("uci")?;
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        loop {
+            let line = engine.read_line(deadline)?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            } else if line.trim() == "uciok" {
+                break;
+            }
+        }
+
+        engine.send("isready")?;
+        while engine.read_line(deadline)?.trim() != "readyok" {}
+
+        Ok(engine)
+    }
+
+    /// Name the engine gave with `id name`, or its path if it didn't
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Starts searching the position reached by playing `moves` from `starting_position`.
+    /// The answer is picked up with `poll_bestmove`
+    pub fn go(
+        &mut self,
+        starting_position: &Position,
+        moves: &[Move],
+        movetime: Duration,
+    ) -> Result<(), String> {
+        self.send(&position_command(starting_position, moves))?;
+        self.send(&format!("go movetime {}", movetime.as_millis()))
+    }
+
+    /// Stops the current search, the move it finds will be ignored
+    pub fn stop(&mut self) -> Result<(), String> {
+        self.stale_searches += 1;
+        self.send("stop")
+    }
+
+    /// Move found by the last `go` in UCI notation, None while the engine is still thinking
+    pub fn poll_bestmove(&mut self) -> Option<Result<String, String>> {
+        let lines = self.lines.get_mut().unwrap();
+        loop {
+            let line = match lines.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => return Some(Err("the engine quit".to_string())),
+            };
+
+            let mut tokens = line.split_whitespace();
+            if tokens.next() != Some("bestmove") {
+                continue;
+            }
+            if self.stale_searches > 0 {
+                self.stale_searches -= 1;
+                continue;
+            }
+            return Some(
+                tokens
+                    .next()
+                    .map(|mv| mv.to_string())
+                    .ok_or_else(|| "the engine sent an empty bestmove".to_string()),
+            );
+        }
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", command)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|error| format!("couldn't write to the engine: {}", error))
+    }
+
+    fn read_line(&mut self, deadline: Instant) -> Result<String, String> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.lines.get_mut().unwrap().recv_timeout(timeout) {
+            Ok(line) => Ok(line),
+            Err(RecvTimeoutError::Timeout) => Err("the engine didn't answer".to_string()),
+            Err(RecvTimeoutError::Disconnected) => Err("the engine quit".to_string()),
+        }
+    }
+}
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        // Give it a moment to quit by itself before killing it
+        for _ in 0..10 {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The `position` command for the position reached by playing `moves` from `starting_position`
+pub fn position_command(starting_position: &Position, moves: &[Move]) -> String {
+    let mut command = format!("position fen {}", starting_position.to_fen());
+    if !moves.is_empty() {
+        command.push_str(" moves");
+        for mv in moves {
+            command.push(' ');
+            command.push_str(&mv.to_uci());
+        }
+    }
+    command
+}
+
+/// External engine playing the computer's side, in place of the built-in search
+pub struct EnginePlayer {
+    pub engine: UciEngine,
+    /// Position the engine is thinking about
+    searching: Option<Position>,
+}
+impl EnginePlayer {
+    pub fn new(engine: UciEngine) -> Self {
+        Self {
+            engine,
+            searching: None,
+        }
+    }
+}
+
+/// Asks the engine for a move when it's the computer's turn.
+/// Runs after the moves' commands are applied, so taken pieces are already marked
+#[allow(clippy::too_many_arguments)]
+fn start_engine_search(
+    mut commands: Commands,
+    engine_player: Option<ResMut<EnginePlayer>>,
+    ai_player: Res<AiPlayer>,
+    starting_position: Res<StartingPosition>,
+    history: Res<MoveHistory>,
+    turn: Res<PlayerTurn>,
+    last_double_push: Res<LastDoublePush>,
+    clocks: Res<MoveClocks>,
+    game_over: Res<GameOver>,
+    replay: Option<Res<Replay>>,
+    pieces_query: Query<&Piece, Without<Taken>>,
+) {
+    let mut engine_player = match engine_player {
+        Some(engine_player) => engine_player,
+        None => return,
+    };
+    if ai_player.color != Some(turn.0)
+        || engine_player.searching.is_some()
+        || game_over.0.is_some()
+        || replay.is_some()
+    {
+        return;
+    }
+
+    let pieces_vec: Vec<Piece> = pieces_query.iter().copied().collect();
+    let position = current_position(&pieces_vec, &turn, &last_double_push, &clocks);
+    let moves: Vec<Move> = history
+        .moves
+        .iter()
+        .map(|played_move| played_move.mv)
+        .collect();
+
+    match engine_player
+        .engine
+        .go(&starting_position.0, &moves, ai_player.think_time)
+    {
+        Ok(()) => engine_player.searching = Some(position),
+        Err(error) => {
+            // The built-in search takes over
+            eprintln!("{}: {}", engine_player.engine.name(), error);
+            commands.remove_resource::<EnginePlayer>();
+        }
+    }
+}
+
+/// Plays the engine's move once it answers, unless the board changed in the meantime
+fn finish_engine_search(
+    mut commands: Commands,
+    engine_player: Option<ResMut<EnginePlayer>>,
+    turn: Res<PlayerTurn>,
+    last_double_push: Res<LastDoublePush>,
+    clocks: Res<MoveClocks>,
+    pieces_query: Query<&Piece, Without<Taken>>,
+    mut move_event: EventWriter<MoveEvent>,
+) {
+    let mut engine_player = match engine_player {
+        Some(engine_player) => engine_player,
+        None => return,
+    };
+    let position = match engine_player.searching.clone() {
+        Some(position) => position,
+        None => return,
+    };
+
+    let pieces_vec: Vec<Piece> = pieces_query.iter().copied().collect();
+    if current_position(&pieces_vec, &turn, &last_double_push, &clocks) != position {
+        // A move was taken back or the game was reset, the answer is useless
+        let _ = engine_player.engine.stop();
+        engine_player.searching = None;
+        return;
+    }
+
+    let result = match engine_player.engine.poll_bestmove() {
+        Some(result) => result,
+        None => return,
+    };
+    engine_player.searching = None;
+
+    match result.and_then(|uci| position.parse_uci(&uci)) {
+        Ok(mv) => move_event.send(MoveEvent(mv)),
+        Err(error) => {
+            // The built-in search takes over
+            eprintln!("{}: {}", engine_player.engine.name(), error);
+            commands.remove_resource::<EnginePlayer>();
+        }
+    }
+}
+
+pub struct UciPlugin;
+impl Plugin for UciPlugin {
+    fn build(&self, app: &mut App) {
+        // The engine doesn't play in the menus or while the game is paused
+        app.add_system_set_to_stage(
+            CoreStage::PostUpdate,
+            SystemSet::new()
+                .with_run_criteria(playing)
+                .with_system(start_engine_search)
+                .with_system(finish_engine_search),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::STARTING_FEN;
+
+    const STUB_ENGINE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/stub_engine.sh");
+
+    fn wait_for_bestmove(engine: &mut UciEngine) -> Result<String, String> {
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Some(result) = engine.poll_bestmove() {
+                return result;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Err("no bestmove".to_string())
+    }
+
+    #[test]
+    fn position_command_lists_the_moves() {
+        let position = Position::from_fen(STARTING_FEN).unwrap();
+        assert_eq!(
+            position_command(&position, &[]),
+            format!("position fen {}", STARTING_FEN)
+        );
+
+        let e4 = position.parse_san("e4").unwrap();
+        let mut after_e4 = position.clone();
+        after_e4.make_move(e4);
+        let moves = [e4, after_e4.parse_san("e5").unwrap()];
+        assert_eq!(
+            position_command(&position, &moves),
+            format!("position fen {} moves e2e4 e7e5", STARTING_FEN)
+        );
+    }
+
+    #[test]
+    fn plays_against_the_stub_engine() {
+        let mut engine = UciEngine::start(STUB_ENGINE).unwrap();
+        assert_eq!(engine.name(), "Stub engine");
+
+        let position = Position::from_fen(STARTING_FEN).unwrap();
+        engine
+            .go(&position, &[], Duration::from_millis(10))
+            .unwrap();
+        let e4 = position.parse_uci(&wait_for_bestmove(&mut engine).unwrap());
+        assert_eq!(e4, position.parse_san("e4"));
+
+        // The answer to a stopped search is skipped
+        engine
+            .go(&position, &[], Duration::from_millis(10))
+            .unwrap();
+        engine.stop().unwrap();
+        engine
+            .go(&position, &[e4.unwrap()], Duration::from_millis(10))
+            .unwrap();
+        assert_eq!(wait_for_bestmove(&mut engine), Ok("e7e5".to_string()));
+    }
+
+    #[test]
+    fn missing_engine_is_reported() {
+        assert!(UciEngine::start("/nonexistent/engine").is_err());
+    }
+}
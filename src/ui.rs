@@ -1,5 +1,19 @@
-use crate::{board::*, pieces::*};
-use bevy::prelude::*;
+use crate::{
+    ai::AiPlayer,
+    board::*,
+    clock::*,
+    menu::AppState,
+    pieces::*,
+    replay::Replay,
+    rules::{Move, Position},
+};
+use bevy::{
+    input::{
+        mouse::{MouseScrollUnit, MouseWheel},
+        InputSystem,
+    },
+    prelude::*,
+};
 
 // Component to mark the Text entity
 #[derive(Component)]
@@ -42,6 +56,73 @@ fn init_next_move_text(
                 .insert(NextMoveText);
 }
 
+// Component to mark the Text entity showing the FEN of the board
+#[derive(Component)]
+struct FenText;
+
+fn init_fen_text(mut commands: Commands, asset_server: ResMut<AssetServer>) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.),
+                    top: Val::Px(60.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font,
+                    font_size: 16.0,
+                    color: Color::rgb(0.8, 0.8, 0.8),
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(FenText);
+}
+
+/// Print the FEN of the board and show it on screen when F is pressed
+fn fen_text_update(
+    keyboard_input: Res<Input<KeyCode>>,
+    turn: Res<PlayerTurn>,
+    last_double_push: Res<LastDoublePush>,
+    clocks: Res<MoveClocks>,
+    pieces_query: Query<&Piece, Without<Taken>>,
+    mut query: Query<&mut Text, With<FenText>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F) {
+        return;
+    }
+
+    let pieces: Vec<Piece> = pieces_query.iter().copied().collect();
+    let fen = current_position(&pieces, &turn, &last_double_push, &clocks).to_fen();
+    println!("{}", fen);
+
+    for mut text in query.iter_mut() {
+        text.sections[0].value = fen.clone();
+    }
+}
+
+/// Clear the FEN of the previous game
+fn clear_fen_text(
+    mut event_reader: EventReader<NewGameEvent>,
+    mut query: Query<&mut Text, With<FenText>>,
+) {
+    if event_reader.iter().last().is_none() {
+        return;
+    }
+    for mut text in query.iter_mut() {
+        text.sections[0].value.clear();
+    }
+}
+
 /// Update text with the correct turn
 fn next_move_text_update(turn: Res<PlayerTurn>, mut query: Query<&mut Text, With<NextMoveText>>) {
     if !turn.is_changed() {
@@ -58,8 +139,715 @@ fn next_move_text_update(turn: Res<PlayerTurn>, mut query: Query<&mut Text, With
     }
 }
 
+/// Replace the turn text with the result once the game ends
+fn game_over_text(
+    mut event_reader: EventReader<GameOverEvent>,
+    mut query: Query<&mut Text, With<NextMoveText>>,
+) {
+    for event in event_reader.iter() {
+        let result = event.0.message();
+        println!("{} Thanks for playing!", result);
+
+        for mut text in query.iter_mut() {
+            text.sections[0].value = result.to_string();
+        }
+    }
+}
+
+// Component to mark the Text entity showing the chess clocks
+#[derive(Component)]
+struct ClockText;
+
+/// Show the clocks beside the turn text, when the game is played with a time control
+fn init_clock_text(
+    mut commands: Commands,
+    asset_server: ResMut<AssetServer>,
+    chess_clocks: Option<Res<ChessClocks>>,
+) {
+    if chess_clocks.is_none() {
+        return;
+    }
+
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(10.),
+                    top: Val::Px(15.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font,
+                    font_size: 30.0,
+                    color: Color::rgb(0.8, 0.8, 0.8),
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(ClockText);
+}
+
+fn clock_text_update(
+    chess_clocks: Option<Res<ChessClocks>>,
+    mut query: Query<&mut Text, With<ClockText>>,
+) {
+    let chess_clocks = match chess_clocks {
+        Some(chess_clocks) => chess_clocks,
+        None => return,
+    };
+
+    let value = format!(
+        "White {}  Black {}",
+        format_clock(chess_clocks.time_left(PieceColor::White)),
+        format_clock(chess_clocks.time_left(PieceColor::Black))
+    );
+    for mut text in query.iter_mut() {
+        // Only touch the text when the shown time changes, so it isn't logged every frame
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+// Component to mark the Text entity showing which side is ahead in material
+#[derive(Component)]
+struct MaterialText;
+
+fn init_material_text(mut commands: Commands, asset_server: ResMut<AssetServer>) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.),
+                    top: Val::Px(85.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font,
+                    font_size: 24.0,
+                    color: Color::rgb(0.8, 0.8, 0.8),
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(MaterialText);
+}
+
+/// Material of White minus material of Black, in points
+fn material_balance(position: &Position) -> i32 {
+    position
+        .to_pieces()
+        .iter()
+        .map(|piece| match piece.color {
+            PieceColor::White => piece.piece_type.points(),
+            PieceColor::Black => -piece.piece_type.points(),
+        })
+        .sum()
+}
+
+/// "White +3" when White is ahead by three points, nothing when material is even
+fn material_balance_text(balance: i32) -> String {
+    match balance {
+        0 => String::new(),
+        balance if balance > 0 => format!("White +{}", balance),
+        balance => format!("Black +{}", -balance),
+    }
+}
+
+fn material_text_update(
+    history: Res<MoveHistory>,
+    starting_position: Res<StartingPosition>,
+    mut query: Query<&mut Text, With<MaterialText>>,
+) {
+    if !history.is_changed() {
+        return;
+    }
+
+    let position = history.moves.last().map_or_else(
+        || starting_position.0.clone(),
+        |last_move| last_move.position_after(),
+    );
+    let value = material_balance_text(material_balance(&position));
+    for mut text in query.iter_mut() {
+        text.sections[0].value = value.clone();
+    }
+}
+
+/// Height of a line of the move list
+const MOVE_ROW_HEIGHT: f32 = 24.;
+/// Height of the part of the move list that's shown, the rest is scrolled to
+const MOVE_LIST_HEIGHT: f32 = 400.;
+
+// Component to mark the root node of the move list
+#[derive(Component)]
+struct MoveList;
+
+// Component to mark the column of rows inside the move list, moved up and down to scroll
+#[derive(Component)]
+struct MoveListRows;
+
+// Component to mark the texts of the move list, they're respawned on every move
+#[derive(Component)]
+struct MoveListText;
+
+// Component for the moves of the move list, with how many moves are played once it's shown
+#[derive(Component)]
+struct MoveButton(usize);
+
+/// How far the move list is scrolled down, in pixels
+#[derive(Default)]
+struct MoveListScroll {
+    offset: f32,
+    max: f32,
+}
+
+/// A line of the move list: the move number, then White's and Black's moves
+#[derive(Debug, PartialEq)]
+struct MoveRow {
+    number: u32,
+    /// SAN of the move, and how many moves are played once it's on the board
+    white: Option<(String, usize)>,
+    black: Option<(String, usize)>,
+}
+
+fn move_rows<'a>(moves: impl Iterator<Item = &'a PlayedMove>) -> Vec<MoveRow> {
+    let mut rows: Vec<MoveRow> = Vec::new();
+    for (i, played_move) in moves.enumerate() {
+        let position = &played_move.position_before;
+        let entry = Some((position.san(played_move.mv), i + 1));
+        match (position.turn, rows.last_mut()) {
+            (PieceColor::Black, Some(row)) if row.black.is_none() => row.black = entry,
+            // Games starting with Black to move leave White's first move empty
+            (PieceColor::Black, _) => rows.push(MoveRow {
+                number: position.fullmove_number,
+                white: None,
+                black: entry,
+            }),
+            (PieceColor::White, _) => rows.push(MoveRow {
+                number: position.fullmove_number,
+                white: entry,
+                black: None,
+            }),
+        }
+    }
+    rows
+}
+
+/// Show the moves of the game beside the board, rebuilt whenever they change.
+/// The move on the board is highlighted, and the ones taken back are dimmed
+fn move_list_update(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    history: Res<MoveHistory>,
+    replay: Option<Res<Replay>>,
+    mut scroll: ResMut<MoveListScroll>,
+    list_query: Query<Entity, With<MoveList>>,
+) {
+    let replay_changed = replay.as_ref().map_or(false, |replay| replay.is_changed());
+    if !history.is_changed() && !replay_changed {
+        return;
+    }
+
+    let (rows, current) = match &replay {
+        Some(replay) => (move_rows(replay.moves().iter()), replay.current()),
+        None => (move_rows(history.all_moves()), history.moves.len()),
+    };
+
+    // Scroll just enough to keep the move on the board in sight
+    let current_row = rows
+        .iter()
+        .position(|row| {
+            [&row.white, &row.black]
+                .iter()
+                .any(|entry| matches!(entry, Some((_, count)) if *count == current))
+        })
+        .unwrap_or(0);
+    let row_top = current_row as f32 * MOVE_ROW_HEIGHT;
+    scroll.max = (rows.len() as f32 * MOVE_ROW_HEIGHT - MOVE_LIST_HEIGHT).max(0.);
+    scroll.offset = scroll
+        .offset
+        .max(row_top + MOVE_ROW_HEIGHT - MOVE_LIST_HEIGHT)
+        .min(row_top)
+        .clamp(0., scroll.max);
+
+    for entity in list_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let text_style = |color| TextStyle {
+        font: font.clone(),
+        font_size: 18.0,
+        color,
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(10.),
+                    top: Val::Px(60.),
+                    ..Default::default()
+                },
+                size: Size::new(Val::Px(170.), Val::Px(MOVE_LIST_HEIGHT)),
+                flex_direction: FlexDirection::ColumnReverse,
+                overflow: Overflow::Hidden,
+                ..Default::default()
+            },
+            color: Color::rgba(0., 0., 0., 0.5).into(),
+            ..Default::default()
+        })
+        .insert(MoveList)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        position: Rect {
+                            top: Val::Px(-scroll.offset),
+                            ..Default::default()
+                        },
+                        flex_direction: FlexDirection::ColumnReverse,
+                        flex_shrink: 0.,
+                        ..Default::default()
+                    },
+                    color: Color::NONE.into(),
+                    ..Default::default()
+                })
+                .insert(MoveListRows)
+                .with_children(|parent| {
+                    for row in rows {
+                        parent
+                            .spawn_bundle(NodeBundle {
+                                style: Style {
+                                    size: Size::new(Val::Px(170.), Val::Px(MOVE_ROW_HEIGHT)),
+                                    align_items: AlignItems::Center,
+                                    flex_shrink: 0.,
+                                    ..Default::default()
+                                },
+                                color: Color::NONE.into(),
+                                ..Default::default()
+                            })
+                            .with_children(|parent| {
+                                parent
+                                    .spawn_bundle(TextBundle {
+                                        style: Style {
+                                            size: Size::new(Val::Px(40.), Val::Auto),
+                                            margin: Rect {
+                                                left: Val::Px(5.),
+                                                ..Default::default()
+                                            },
+                                            ..Default::default()
+                                        },
+                                        text: Text::with_section(
+                                            format!("{}.", row.number),
+                                            text_style(Color::rgb(0.6, 0.6, 0.6)),
+                                            Default::default(),
+                                        ),
+                                        ..Default::default()
+                                    })
+                                    .insert(MoveListText);
+
+                                for entry in [row.white, row.black] {
+                                    let (san, count) =
+                                        entry.unwrap_or_else(|| ("...".to_string(), 0));
+                                    let (color, text_color) = if count == 0 {
+                                        (Color::NONE, Color::rgb(0.6, 0.6, 0.6))
+                                    } else if count == current {
+                                        (Color::rgb(0.3, 0.3, 0.6), Color::rgb(0.9, 0.9, 0.9))
+                                    } else if count > current {
+                                        (Color::NONE, Color::rgb(0.45, 0.45, 0.45))
+                                    } else {
+                                        (Color::NONE, Color::rgb(0.8, 0.8, 0.8))
+                                    };
+
+                                    let mut button = parent.spawn_bundle(ButtonBundle {
+                                        style: Style {
+                                            size: Size::new(Val::Px(60.), Val::Px(MOVE_ROW_HEIGHT)),
+                                            align_items: AlignItems::Center,
+                                            padding: Rect {
+                                                left: Val::Px(4.),
+                                                ..Default::default()
+                                            },
+                                            ..Default::default()
+                                        },
+                                        color: color.into(),
+                                        ..Default::default()
+                                    });
+                                    if count > 0 {
+                                        button.insert(MoveButton(count));
+                                    }
+                                    button.with_children(|parent| {
+                                        parent
+                                            .spawn_bundle(TextBundle {
+                                                text: Text::with_section(
+                                                    san,
+                                                    text_style(text_color),
+                                                    Default::default(),
+                                                ),
+                                                ..Default::default()
+                                            })
+                                            .insert(MoveListText);
+                                    });
+                                }
+                            });
+                    }
+                });
+        });
+}
+
+/// Scroll the move list with the mouse wheel
+fn scroll_move_list(
+    mut wheel_events: EventReader<MouseWheel>,
+    mut scroll: ResMut<MoveListScroll>,
+    mut rows_query: Query<&mut Style, With<MoveListRows>>,
+) {
+    let mut offset = scroll.offset;
+    for event in wheel_events.iter() {
+        offset -= match event.unit {
+            MouseScrollUnit::Line => event.y * MOVE_ROW_HEIGHT,
+            MouseScrollUnit::Pixel => event.y,
+        };
+    }
+    offset = offset.clamp(0., scroll.max);
+    if offset == scroll.offset {
+        return;
+    }
+
+    scroll.offset = offset;
+    for mut style in rows_query.iter_mut() {
+        style.position.top = Val::Px(-offset);
+    }
+}
+
+/// Show the position after the move clicked in the move list
+fn move_button_click(
+    query: Query<(&Interaction, &MoveButton), Changed<Interaction>>,
+    mut jump_event: EventWriter<JumpToMoveEvent>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction == Interaction::Clicked {
+            jump_event.send(JumpToMoveEvent(button.0));
+        }
+    }
+}
+
+// Component to mark the root node of the promotion picker
+#[derive(Component)]
+struct PromotionPicker;
+
+// Component for the picker buttons, with the piece they promote to
+#[derive(Component)]
+struct PromotionButton(PieceType);
+
+/// Show the promotion picker while a pawn is waiting to be promoted
+fn promotion_picker(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    pending_promotion: Res<PendingPromotion>,
+    picker_query: Query<Entity, With<PromotionPicker>>,
+) {
+    if !pending_promotion.is_changed() {
+        return;
+    }
+
+    // Remove the previous picker, if there was one
+    for entity in picker_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    if pending_promotion.0.is_none() {
+        return;
+    }
+
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.),
+                    bottom: Val::Px(10.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .insert(PromotionPicker)
+        .with_children(|parent| {
+            for (piece_type, name) in [
+                (PieceType::Queen, "Queen"),
+                (PieceType::Rook, "Rook"),
+                (PieceType::Bishop, "Bishop"),
+                (PieceType::Knight, "Knight"),
+            ] {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            margin: Rect::all(Val::Px(5.)),
+                            padding: Rect::all(Val::Px(5.)),
+                            ..Default::default()
+                        },
+                        color: Color::rgb(0.15, 0.15, 0.15).into(),
+                        ..Default::default()
+                    })
+                    .insert(PromotionButton(piece_type))
+                    .with_children(|parent| {
+                        parent.spawn_bundle(TextBundle {
+                            text: Text::with_section(
+                                name,
+                                TextStyle {
+                                    font: font.clone(),
+                                    font_size: 30.0,
+                                    color: Color::rgb(0.8, 0.8, 0.8),
+                                },
+                                Default::default(),
+                            ),
+                            ..Default::default()
+                        });
+                    });
+            }
+        });
+}
+
+/// Send the piece chosen in the promotion picker
+fn promotion_button_click(
+    query: Query<(&Interaction, &PromotionButton), Changed<Interaction>>,
+    mut promotion_event: EventWriter<PromotionEvent>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction == Interaction::Clicked {
+            promotion_event.send(PromotionEvent(button.0));
+        }
+    }
+}
+
+/// Move typed in the move box, and why the last one couldn't be played
+#[derive(Default)]
+struct MoveInput {
+    /// While the box has focus, the keyboard types into it instead of using the shortcuts
+    focused: bool,
+    text: String,
+    error: Option<String>,
+}
+
+// Component to mark the move box, a button so it can be clicked to type in it
+#[derive(Component)]
+struct MoveInputBox;
+
+// Component to mark the Text entity of the move box
+#[derive(Component)]
+struct MoveInputText;
+
+fn init_move_input(mut commands: Commands, asset_server: ResMut<AssetServer>) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+
+    commands
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(10.),
+                    bottom: Val::Px(10.),
+                    ..Default::default()
+                },
+                size: Size::new(Val::Px(170.), Val::Auto),
+                padding: Rect::all(Val::Px(5.)),
+                ..Default::default()
+            },
+            color: Color::rgb(0.15, 0.15, 0.15).into(),
+            ..Default::default()
+        })
+        .insert(MoveInputBox)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text {
+                        sections: vec![
+                            TextSection {
+                                value: String::new(),
+                                style: TextStyle {
+                                    font: font.clone(),
+                                    font_size: 20.0,
+                                    color: Color::rgb(0.8, 0.8, 0.8),
+                                },
+                            },
+                            // Errors go under the move
+                            TextSection {
+                                value: String::new(),
+                                style: TextStyle {
+                                    font,
+                                    font_size: 16.0,
+                                    color: Color::rgb(0.9, 0.4, 0.4),
+                                },
+                            },
+                        ],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(MoveInputText);
+        });
+}
+
+/// Finds the move typed in the box. Pawns reaching the last row can be typed without
+/// their promotion, the promotion picker asks for it like when moving with the mouse
+fn parse_move_input(position: &Position, text: &str) -> Result<Move, String> {
+    position.parse_san(text).or_else(|error| {
+        position
+            .parse_san(&format!("{}=Q", text))
+            .map(|mv| Move {
+                promotion: None,
+                ..mv
+            })
+            .map_err(|_| error)
+    })
+}
+
+/// Tab or a click on the move box starts typing a move, Enter plays it and
+/// Escape or Tab leave the box. Runs before every other system, so the keys
+/// typed in the box don't trigger the shortcuts too
+#[allow(clippy::too_many_arguments)]
+fn type_move(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut move_input: ResMut<MoveInput>,
+    state: Res<State<AppState>>,
+    turn: Res<PlayerTurn>,
+    last_double_push: Res<LastDoublePush>,
+    clocks: Res<MoveClocks>,
+    game_over: Res<GameOver>,
+    mut pending_promotion: ResMut<PendingPromotion>,
+    replay: Option<Res<Replay>>,
+    ai_player: Option<Res<AiPlayer>>,
+    pieces_query: Query<&Piece, Without<Taken>>,
+    mut move_event: EventWriter<MoveEvent>,
+) {
+    if state.current() != &AppState::Playing {
+        if move_input.focused {
+            move_input.focused = false;
+        }
+        return;
+    }
+
+    if !move_input.focused {
+        if keyboard_input.just_pressed(KeyCode::Tab) {
+            move_input.focused = true;
+            keyboard_input.clear();
+        }
+        // Nothing typed before the box has focus
+        characters.iter().for_each(drop);
+        return;
+    }
+
+    for event in characters.iter() {
+        if !event.char.is_control() && !event.char.is_whitespace() {
+            move_input.text.push(event.char);
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        move_input.text.pop();
+    }
+    if keyboard_input.just_pressed(KeyCode::Escape) || keyboard_input.just_pressed(KeyCode::Tab) {
+        move_input.focused = false;
+        move_input.text.clear();
+        move_input.error = None;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Return) && !move_input.text.is_empty() {
+        let (replaying, ai_player) = (replay.is_some(), ai_player.as_deref());
+        let locked = board_locked(&pending_promotion, &game_over, replaying, ai_player, &turn);
+
+        let pieces_vec: Vec<Piece> = pieces_query.iter().copied().collect();
+        let position = current_position(&pieces_vec, &turn, &last_double_push, &clocks);
+        let result = if locked {
+            Err("Moves can't be played now".to_string())
+        } else {
+            parse_move_input(&position, &move_input.text)
+        };
+
+        match result {
+            Ok(mv) => {
+                let promoting = mv.to.0 == 0 || mv.to.0 == 7;
+                let pawn = position.piece_at(mv.from).map(|piece| piece.1) == Some(PieceType::Pawn);
+                if pawn && promoting && mv.promotion.is_none() {
+                    pending_promotion.0 = Some(mv);
+                } else {
+                    move_event.send(MoveEvent(mv));
+                }
+                move_input.text.clear();
+                move_input.error = None;
+            }
+            Err(error) => move_input.error = Some(error),
+        }
+    }
+
+    // The keys were for the box
+    keyboard_input.clear();
+}
+
+/// Clicking the move box starts typing a move
+fn move_input_click(
+    query: Query<&Interaction, (Changed<Interaction>, With<MoveInputBox>)>,
+    state: Res<State<AppState>>,
+    mut move_input: ResMut<MoveInput>,
+) {
+    let clicked = query
+        .iter()
+        .any(|interaction| *interaction == Interaction::Clicked);
+    if clicked && state.current() == &AppState::Playing {
+        move_input.focused = true;
+    }
+}
+
+fn move_input_text_update(
+    move_input: Res<MoveInput>,
+    mut query: Query<&mut Text, With<MoveInputText>>,
+) {
+    if !move_input.is_changed() {
+        return;
+    }
+
+    let value = if move_input.focused {
+        format!("{}_", move_input.text)
+    } else {
+        "Tab to type a move".to_string()
+    };
+    let error = move_input
+        .error
+        .as_ref()
+        .map_or_else(String::new, |error| format!("\n{}", error));
+    for mut text in query.iter_mut() {
+        text.sections[0].value = value.clone();
+        text.sections[1].value = error.clone();
+    }
+}
+
 /// Demo system to show off Query transformers
-fn log_text_changes(query: Query<&Text, Changed<Text>>) {
+#[allow(clippy::type_complexity)]
+fn log_text_changes(
+    query: Query<&Text, (Changed<Text>, Without<MoveListText>, Without<ClockText>)>,
+) {
     for text in query.iter() {
         println!("New text: {}", text.sections[0].value);
     }
@@ -69,7 +857,124 @@ pub struct UIPlugin;
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
         app.add_startup_system(init_next_move_text)
+            .add_startup_system(init_fen_text)
+            .add_startup_system(init_clock_text)
+            .add_startup_system(init_material_text)
+            .add_startup_system(init_move_input)
+            .init_resource::<MoveListScroll>()
+            .init_resource::<MoveInput>()
+            .add_system_to_stage(CoreStage::PreUpdate, type_move.after(InputSystem))
             .add_system(next_move_text_update)
+            .add_system(game_over_text.after(next_move_text_update))
+            .add_system(fen_text_update)
+            .add_system(clear_fen_text)
+            .add_system(clock_text_update)
+            .add_system(material_text_update)
+            .add_system(move_list_update)
+            .add_system(scroll_move_list)
+            .add_system(move_button_click)
+            .add_system(move_input_click)
+            .add_system(move_input_text_update)
+            .add_system(promotion_picker)
+            .add_system(promotion_button_click)
             .add_system(log_text_changes);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Position;
+
+    fn played_moves(fen: &str, sans: &[&str]) -> Vec<PlayedMove> {
+        let mut position = Position::from_fen(fen).unwrap();
+        sans.iter()
+            .map(|san| {
+                let played_move = PlayedMove::new(&position, position.parse_san(san).unwrap());
+                position = played_move.position_after();
+                played_move
+            })
+            .collect()
+    }
+
+    #[test]
+    fn material_balance_counts_points() {
+        let position = Position::from_fen(crate::rules::STARTING_FEN).unwrap();
+        assert_eq!(material_balance_text(material_balance(&position)), "");
+
+        // White is up a rook for a knight, Black has an extra pawn
+        let position = Position::from_fen("4k3/pp6/2n5/8/8/8/P7/R3K3 w - - 0 1").unwrap();
+        assert_eq!(material_balance(&position), 1);
+        assert_eq!(material_balance_text(1), "White +1");
+        assert_eq!(material_balance_text(-3), "Black +3");
+    }
+
+    #[test]
+    fn parses_typed_moves() {
+        let position = Position::from_fen(crate::rules::STARTING_FEN).unwrap();
+        assert_eq!(
+            parse_move_input(&position, "Nf3").map(|mv| mv.to_uci()),
+            Ok("g1f3".to_string())
+        );
+        assert_eq!(
+            parse_move_input(&position, "e5"),
+            Err("e5 is not a legal move".to_string())
+        );
+
+        let position = Position::from_fen("4k3/1P6/8/8/8/8/8/1N2KN2 w - - 0 1").unwrap();
+        assert_eq!(
+            parse_move_input(&position, "Nd2"),
+            Err("Nd2 is ambiguous".to_string())
+        );
+        assert_eq!(
+            parse_move_input(&position, "Nbd2").map(|mv| mv.to_uci()),
+            Ok("b1d2".to_string())
+        );
+        // The promotion picker asks for the piece when it's left out
+        assert_eq!(
+            parse_move_input(&position, "b8").map(|mv| mv.to_uci()),
+            Ok("b7b8".to_string())
+        );
+        assert_eq!(
+            parse_move_input(&position, "b8=N").map(|mv| mv.to_uci()),
+            Ok("b7b8n".to_string())
+        );
+    }
+
+    #[test]
+    fn move_rows_pair_the_moves_by_number() {
+        let moves = played_moves(crate::rules::STARTING_FEN, &["e4", "e5", "Nf3"]);
+        assert_eq!(
+            move_rows(moves.iter()),
+            vec![
+                MoveRow {
+                    number: 1,
+                    white: Some(("e4".to_string(), 1)),
+                    black: Some(("e5".to_string(), 2)),
+                },
+                MoveRow {
+                    number: 2,
+                    white: Some(("Nf3".to_string(), 3)),
+                    black: None,
+                },
+            ]
+        );
+
+        let moves = played_moves("4k3/8/8/8/8/8/4P3/4K3 b - - 0 12", &["Kd7", "e4"]);
+        assert_eq!(
+            move_rows(moves.iter()),
+            vec![
+                MoveRow {
+                    number: 12,
+                    white: None,
+                    black: Some(("Kd7".to_string(), 1)),
+                },
+                MoveRow {
+                    number: 13,
+                    white: Some(("e4".to_string(), 2)),
+                    black: None,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,27 @@
+#!/bin/sh
+# Smallest UCI engine the tests can talk to: it always answers with the same move,
+# e2e4 from the starting position and e7e5 once a move was played
+while read -r line; do
+    case "$line" in
+        uci)
+            echo "id name Stub engine"
+            echo "uciok"
+            ;;
+        isready)
+            echo "readyok"
+            ;;
+        position*moves*)
+            bestmove="e7e5"
+            ;;
+        position*)
+            bestmove="e2e4"
+            ;;
+        go*)
+            echo "info depth 1 score cp 0"
+            echo "bestmove $bestmove"
+            ;;
+        quit)
+            exit 0
+            ;;
+    esac
+done